@@ -1,9 +1,10 @@
 use paste::paste;
-use typst::diag::HintedString;
+use typst::diag::{EcoString, HintedString};
 use typst::foundations::Symbol;
 use typst::foundations::{
-    Args, Array, Bytes, Content, Datetime, Dict, Duration, FromValue,
-    Func, Label, Module, Scope, Smart, Str, Styles, Type, Version,
+    Args, Array, Binding, Bytes, Content, Datetime, Dict, Duration,
+    FromValue, Func, Label, Module, Scope, Smart, Str, Styles, Type,
+    Version,
 };
 use typst::layout::{Abs, Angle, Em, Fr, Length, Ratio, Rel, Sizing};
 use typst::visualize::{Color, Gradient, Tiling};
@@ -46,6 +47,38 @@ impl UnitExt for Em {
     }
 }
 
+impl UnitExt for Length {
+    fn length(self) -> Length {
+        self
+    }
+
+    fn rel(self) -> Rel {
+        Rel::from(self)
+    }
+}
+
+/// A number of points.
+impl UnitExt for f32 {
+    fn length(self) -> Length {
+        (self as f64).length()
+    }
+
+    fn rel(self) -> Rel {
+        (self as f64).rel()
+    }
+}
+
+/// A number of points.
+impl UnitExt for f64 {
+    fn length(self) -> Length {
+        Abs::pt(self).length()
+    }
+
+    fn rel(self) -> Rel {
+        Abs::pt(self).rel()
+    }
+}
+
 /// Implement [ScopeExt::get_value()] and
 /// [ScopeExt::get_value_unchecked()] function for given values.
 macro_rules! fn_get_value {
@@ -84,6 +117,28 @@ pub trait ScopeExt {
         var: &str,
     ) -> Result<T, ScopeError>;
 
+    /// The binding at a dotted path, such as `ui.hud.health`.
+    ///
+    /// Every segment but the last must name a value with a scope,
+    /// such as a module, including imported packages.
+    fn get_binding(&self, path: &str)
+    -> Result<&Binding, ScopeError>;
+
+    /// Clone a variable at a dotted path, such as `ui.hud.health`,
+    /// and cast it into the final value.
+    ///
+    /// See [ScopeExt::get_binding()].
+    fn get_path<T: FromValue>(
+        &self,
+        path: &str,
+    ) -> Result<T, ScopeError> {
+        self.get_binding(path)?
+            .read()
+            .clone()
+            .cast::<T>()
+            .map_err(ScopeError::ValueCastFailed)
+    }
+
     fn_get_value!(
         (get_bool, bool),
         (get_int, i64),
@@ -133,11 +188,35 @@ impl ScopeExt for Scope {
             },
         )
     }
+
+    fn get_binding(
+        &self,
+        path: &str,
+    ) -> Result<&Binding, ScopeError> {
+        let mut segments = path.split('.');
+        let mut parent = segments.next().unwrap_or_default();
+        let mut binding =
+            self.get(parent).ok_or(ScopeError::VariableNotFound)?;
+
+        for segment in segments {
+            binding = binding
+                .read()
+                .scope()
+                .ok_or_else(|| ScopeError::NotAModule(parent.into()))?
+                .get(segment)
+                .ok_or(ScopeError::VariableNotFound)?;
+            parent = segment;
+        }
+        Ok(binding)
+    }
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ScopeError {
     VariableNotFound,
+    /// A segment of a dotted path is not a module.
+    NotAModule(EcoString),
     ValueCastFailed(HintedString),
 }
 
@@ -150,6 +229,9 @@ impl std::fmt::Display for ScopeError {
             ScopeError::VariableNotFound => {
                 f.pad("Variable not found!")
             }
+            ScopeError::NotAModule(segment) => {
+                write!(f, "`{segment}` is not a module!")
+            }
             ScopeError::ValueCastFailed(hinted_string) => write!(
                 f,
                 "Cast fail! {}\n{}",
@@ -159,3 +241,60 @@ impl std::fmt::Display for ScopeError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use typst::foundations::{IntoValue, Value};
+
+    use super::*;
+
+    /// `ui.hud.health` with `ui` and `hud` as modules.
+    fn scope() -> Scope {
+        let mut hud = Scope::new();
+        hud.define("health", 3);
+        let mut ui = Scope::new();
+        ui.define("hud", Module::new("hud", hud));
+        ui.define("title", "Game");
+        let mut scope = Scope::new();
+        scope.define("ui", Module::new("ui", ui));
+        scope
+    }
+
+    #[test]
+    fn get_path_resolves_dotted_paths() {
+        let scope = scope();
+        assert_eq!(
+            scope.get_path::<i64>("ui.hud.health").unwrap(),
+            3
+        );
+        assert_eq!(
+            scope.get_path::<Str>("ui.title").unwrap(),
+            Str::from("Game")
+        );
+        assert_eq!(
+            scope.get_binding("ui.hud.health").unwrap().read(),
+            &3.into_value()
+        );
+        assert!(matches!(
+            scope.get_path::<Value>("ui"),
+            Ok(Value::Module(_))
+        ));
+    }
+
+    #[test]
+    fn get_path_errors() {
+        let scope = scope();
+        assert!(matches!(
+            scope.get_path::<i64>("ui.hud.armor"),
+            Err(ScopeError::VariableNotFound)
+        ));
+        assert!(matches!(
+            scope.get_path::<i64>("ui.title.len"),
+            Err(ScopeError::NotAModule(segment)) if segment == "title"
+        ));
+        assert!(matches!(
+            scope.get_path::<i64>("ui.title"),
+            Err(ScopeError::ValueCastFailed(_))
+        ));
+    }
+}
@@ -14,3 +14,5 @@ pub mod prelude {
 
 pub mod elem;
 pub mod extensions;
+#[cfg(feature = "serde")]
+pub mod serde;
@@ -0,0 +1,1133 @@
+//! Conversion between [`Serialize`]/[`Deserialize`] types and Typst
+//! [`Value`]s.
+//!
+//! Structs and maps become dictionaries, sequences and tuples become
+//! arrays, and enums are externally tagged: unit variants become
+//! strings and other variants become single-entry dictionaries, e.g.
+//! `(potion: 3)`.
+//!
+//! # Example
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use typst_element::serde::{from_value, to_value};
+//!
+//! #[derive(Serialize, Deserialize, PartialEq, Debug)]
+//! struct Item {
+//!     name: String,
+//!     count: u32,
+//! }
+//!
+//! let item = Item {
+//!     name: "Potion".into(),
+//!     count: 3,
+//! };
+//! let value = to_value(&item).unwrap();
+//! assert_eq!(from_value::<Item>(value).unwrap(), item);
+//! ```
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use ::serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess,
+    IntoDeserializer, MapAccess, SeqAccess, Unexpected,
+    VariantAccess, Visitor,
+};
+use ::serde::ser::{self, Impossible, Serialize};
+use ::serde::{Deserializer, Serializer};
+use typst::diag::{EcoString, HintedStrResult, eco_format};
+use typst::foundations::{
+    Array, Bytes, CastInfo, Dict, FromValue, IntoValue, Reflect, Str,
+    Value, dict,
+};
+
+/// Serialize a value into a Typst [`Value`].
+pub fn to_value<T: Serialize + ?Sized>(
+    value: &T,
+) -> Result<Value, Error> {
+    value.serialize(ValueSerializer)
+}
+
+/// Deserialize a value from a Typst [`Value`].
+pub fn from_value<T: DeserializeOwned>(
+    value: Value,
+) -> Result<T, Error> {
+    T::deserialize(ValueDeserializer(value))
+}
+
+/// Wraps a [`Serialize`] or [`Deserialize`] type so that it can be
+/// used as a Typst value, e.g. as an argument of a `TypstFunc` or as
+/// the result of a query.
+///
+/// [`IntoValue`] logs an error and returns [`Value::None`] if the
+/// value cannot be serialized, e.g. when a map has keys that are not
+/// strings, numbers, booleans or chars. Use [`to_value`] to handle
+/// the error instead.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Serde<T>(pub T);
+
+impl<T: Serialize> IntoValue for Serde<T> {
+    fn into_value(self) -> Value {
+        to_value(&self.0).unwrap_or_else(|err| {
+            log::error!(
+                "Unable to serialize into a Typst value: {err}"
+            );
+            Value::None
+        })
+    }
+}
+
+impl<T> Reflect for Serde<T> {
+    fn input() -> CastInfo {
+        CastInfo::Any
+    }
+
+    fn output() -> CastInfo {
+        CastInfo::Any
+    }
+
+    fn castable(_: &Value) -> bool {
+        true
+    }
+}
+
+impl<T: DeserializeOwned> FromValue for Serde<T> {
+    fn from_value(value: Value) -> HintedStrResult<Self> {
+        from_value(value).map(Self).map_err(|err| err.0.into())
+    }
+}
+
+/// An error while converting between Rust types and Typst values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(pub EcoString);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self(eco_format!("{msg}"))
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self(eco_format!("{msg}"))
+    }
+}
+
+/// A [`Serializer`] whose output is a Typst [`Value`].
+pub struct ValueSerializer;
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeVariant<SerializeArray>;
+    type SerializeMap = SerializeDict;
+    type SerializeStruct = SerializeDict;
+    type SerializeStructVariant = SerializeVariant<SerializeDict>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        Ok(Value::Int(v))
+    }
+
+    /// Integers beyond the range of `i64` become floats.
+    fn serialize_i128(self, v: i128) -> Result<Value, Error> {
+        Ok(i64::try_from(v)
+            .map_or(Value::Float(v as f64), Value::Int))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        Ok(Value::Int(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        self.serialize_u128(v.into())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Value, Error> {
+        Ok(i64::try_from(v)
+            .map_or(Value::Float(v as f64), Value::Int))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        Ok(Value::Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        Ok(Value::Str(v.into()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        Ok(Value::Str(v.into()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        Ok(Value::Bytes(Bytes::new(v.to_vec())))
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::None)
+    }
+
+    fn serialize_unit_struct(
+        self,
+        _name: &'static str,
+    ) -> Result<Value, Error> {
+        Ok(Value::None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Error> {
+        Ok(Value::Str(variant.into()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        Ok(Value::Dict(dict! { variant => to_value(value)? }))
+    }
+
+    fn serialize_seq(
+        self,
+        len: Option<usize>,
+    ) -> Result<SerializeArray, Error> {
+        Ok(SerializeArray(Vec::with_capacity(
+            len.unwrap_or_default(),
+        )))
+    }
+
+    fn serialize_tuple(
+        self,
+        len: usize,
+    ) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeArray>, Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(
+        self,
+        _len: Option<usize>,
+    ) -> Result<SerializeDict, Error> {
+        Ok(SerializeDict::default())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeDict, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeDict>, Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+/// Serializes sequences and tuples into an [`Array`].
+pub struct SerializeArray(Vec<Value>);
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.0.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::Array(self.0.into_iter().collect()))
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+/// Serializes maps and structs into a [`Dict`].
+#[derive(Default)]
+pub struct SerializeDict {
+    dict: Dict,
+    key: Option<Str>,
+}
+
+impl ser::SerializeMap for SerializeDict {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(
+        &mut self,
+        key: &T,
+    ) -> Result<(), Error> {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Error> {
+        let key = self.key.take().ok_or_else(|| {
+            Error("map value serialized before its key".into())
+        })?;
+        self.dict.insert(key, to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::Dict(self.dict))
+    }
+}
+
+impl ser::SerializeStruct for SerializeDict {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.dict.insert(key.into(), to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::Dict(self.dict))
+    }
+}
+
+/// Serializes an enum variant into a single-entry [`Dict`].
+pub struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeArray> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        let value = ser::SerializeSeq::end(self.inner)?;
+        Ok(Value::Dict(dict! { self.variant => value }))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeDict> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(
+            &mut self.inner,
+            key,
+            value,
+        )
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        let value = ser::SerializeStruct::end(self.inner)?;
+        Ok(Value::Dict(dict! { self.variant => value }))
+    }
+}
+
+/// Serializes map keys into dictionary keys. Numbers, booleans and
+/// chars are converted into strings.
+struct KeySerializer;
+
+impl KeySerializer {
+    fn display(v: impl Display) -> Result<Str, Error> {
+        Ok(eco_format!("{v}").into())
+    }
+
+    fn unsupported(kind: &str) -> Error {
+        Error(eco_format!(
+            "dictionary keys must be strings, found {kind}"
+        ))
+    }
+}
+
+impl Serializer for KeySerializer {
+    type Ok = Str;
+    type Error = Error;
+
+    type SerializeSeq = Impossible<Str, Error>;
+    type SerializeTuple = Impossible<Str, Error>;
+    type SerializeTupleStruct = Impossible<Str, Error>;
+    type SerializeTupleVariant = Impossible<Str, Error>;
+    type SerializeMap = Impossible<Str, Error>;
+    type SerializeStruct = Impossible<Str, Error>;
+    type SerializeStructVariant = Impossible<Str, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Str, Error> {
+        Self::display(v)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Str, Error> {
+        Err(Self::unsupported("a float"))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Str, Error> {
+        Err(Self::unsupported("a float"))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Str, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Str, Error> {
+        Ok(v.into())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Str, Error> {
+        Err(Self::unsupported("bytes"))
+    }
+
+    fn serialize_none(self) -> Result<Str, Error> {
+        Err(Self::unsupported("none"))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<Str, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Str, Error> {
+        Err(Self::unsupported("none"))
+    }
+
+    fn serialize_unit_struct(
+        self,
+        _name: &'static str,
+    ) -> Result<Str, Error> {
+        Err(Self::unsupported("none"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Str, Error> {
+        Ok(variant.into())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Str, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Str, Error> {
+        Err(Self::unsupported("an enum variant with data"))
+    }
+
+    fn serialize_seq(
+        self,
+        _len: Option<usize>,
+    ) -> Result<Self::SerializeSeq, Error> {
+        Err(Self::unsupported("an array"))
+    }
+
+    fn serialize_tuple(
+        self,
+        _len: usize,
+    ) -> Result<Self::SerializeTuple, Error> {
+        Err(Self::unsupported("an array"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(Self::unsupported("an array"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(Self::unsupported("an enum variant with data"))
+    }
+
+    fn serialize_map(
+        self,
+        _len: Option<usize>,
+    ) -> Result<Self::SerializeMap, Error> {
+        Err(Self::unsupported("a dictionary"))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Err(Self::unsupported("a dictionary"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(Self::unsupported("an enum variant with data"))
+    }
+}
+
+/// A [`Deserializer`] that reads from a Typst [`Value`].
+///
+/// Values without a serde equivalent, such as lengths, colors or
+/// content, cannot be deserialized.
+pub struct ValueDeserializer(pub Value);
+
+impl ValueDeserializer {
+    fn invalid_type(&self, exp: &dyn de::Expected) -> Error {
+        let ty = self.0.ty();
+        let unexpected = match &self.0 {
+            Value::Bool(v) => Unexpected::Bool(*v),
+            Value::Int(v) => Unexpected::Signed(*v),
+            Value::Float(v) => Unexpected::Float(*v),
+            Value::Str(v) => Unexpected::Str(v),
+            Value::Bytes(v) => Unexpected::Bytes(v.as_slice()),
+            Value::None => Unexpected::Unit,
+            Value::Array(_) => Unexpected::Seq,
+            Value::Dict(_) => Unexpected::Map,
+            _ => Unexpected::Other(ty.short_name()),
+        };
+        de::Error::invalid_type(unexpected, exp)
+    }
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::None | Value::Auto => visitor.visit_unit(),
+            Value::Bool(v) => visitor.visit_bool(v),
+            Value::Int(v) => visitor.visit_i64(v),
+            Value::Float(v) => visitor.visit_f64(v),
+            Value::Str(v) => visitor.visit_string(v.into()),
+            Value::Bytes(v) => {
+                visitor.visit_byte_buf(v.as_slice().to_vec())
+            }
+            Value::Array(v) => visitor.visit_seq(ArrayAccess {
+                iter: v.into_iter(),
+            }),
+            Value::Dict(v) => visitor.visit_map(DictAccess {
+                iter: v.into_iter(),
+                value: None,
+            }),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::None => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::Str(variant) => {
+                visitor.visit_enum(VariantDeserializer {
+                    variant,
+                    value: None,
+                })
+            }
+            Value::Dict(dict) if dict.len() == 1 => {
+                let (variant, value) = dict
+                    .into_iter()
+                    .next()
+                    .expect("dict has one entry");
+                visitor.visit_enum(VariantDeserializer {
+                    variant,
+                    value: Some(value),
+                })
+            }
+            _ => Err(self.invalid_type(
+                &"a string or a dictionary with a single key",
+            )),
+        }
+    }
+
+    ::serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str
+        string bytes byte_buf unit unit_struct seq tuple tuple_struct
+        map struct identifier ignored_any
+    }
+}
+
+impl IntoDeserializer<'_, Error> for ValueDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+struct ArrayAccess {
+    iter: <Array as IntoIterator>::IntoIter,
+}
+
+impl<'de> SeqAccess<'de> for ArrayAccess {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        self.iter
+            .next()
+            .map(|value| seed.deserialize(ValueDeserializer(value)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct DictAccess {
+    iter: <Dict as IntoIterator>::IntoIter,
+    value: Option<Value>,
+}
+
+impl<'de> MapAccess<'de> for DictAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let Some((key, value)) = self.iter.next() else {
+            return Ok(None);
+        };
+        self.value = Some(value);
+        seed.deserialize(KeyDeserializer(key)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Error> {
+        let value = self.value.take().ok_or_else(|| {
+            Error("map value deserialized before its key".into())
+        })?;
+        seed.deserialize(ValueDeserializer(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// Deserializes dictionary keys, parsing the numbers, booleans and
+/// chars that [`KeySerializer`] converted into strings.
+struct KeyDeserializer(Str);
+
+impl KeyDeserializer {
+    fn parse<T: FromStr>(
+        &self,
+        exp: &dyn de::Expected,
+    ) -> Result<T, Error> {
+        self.0.parse().map_err(|_| {
+            de::Error::invalid_value(Unexpected::Str(&self.0), exp)
+        })
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                visitor: V,
+            ) -> Result<V::Value, Error> {
+                let value = self.parse(&visitor)?;
+                visitor.$visit(value)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for KeyDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_string(self.0.into())
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(VariantDeserializer {
+            variant: self.0,
+            value: None,
+        })
+    }
+
+    ::serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Deserializes an externally tagged enum variant.
+struct VariantDeserializer {
+    variant: Str,
+    value: Option<Value>,
+}
+
+impl<'de> EnumAccess<'de> for VariantDeserializer {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), Error> {
+        let variant = seed.deserialize(ValueDeserializer(
+            Value::Str(self.variant.clone()),
+        ))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.value {
+            None | Some(Value::None) => Ok(()),
+            Some(value) => Err(ValueDeserializer(value)
+                .invalid_type(&"a unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Error> {
+        seed.deserialize(ValueDeserializer(
+            self.value.unwrap_or_default(),
+        ))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        ValueDeserializer(self.value.unwrap_or_default())
+            .deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        ValueDeserializer(self.value.unwrap_or_default())
+            .deserialize_map(visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ::serde::{Deserialize, Serialize};
+    use typst::foundations::array;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Item {
+        name: String,
+        count: u32,
+        tags: Vec<String>,
+        rarity: Rarity,
+        effect: Option<Effect>,
+    }
+
+    #[derive(
+        Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord,
+    )]
+    enum Rarity {
+        Common,
+        Rare,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Effect {
+        Heal(i64),
+        Buff { stat: String, amount: f64 },
+        Pair(u8, u8),
+    }
+
+    fn item() -> Item {
+        Item {
+            name: "Potion".into(),
+            count: 3,
+            tags: vec!["consumable".into()],
+            rarity: Rarity::Rare,
+            effect: Some(Effect::Heal(20)),
+        }
+    }
+
+    #[test]
+    fn structs_become_dicts() {
+        let value = to_value(&item()).unwrap();
+        assert_eq!(
+            value,
+            Value::Dict(dict! {
+                "name" => "Potion",
+                "count" => 3,
+                "tags" => array!["consumable"],
+                "rarity" => "Rare",
+                "effect" => dict! { "Heal" => 20 },
+            })
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let item = item();
+        assert_eq!(
+            from_value::<Item>(to_value(&item).unwrap()).unwrap(),
+            item
+        );
+
+        for effect in [
+            Effect::Heal(-1),
+            Effect::Buff {
+                stat: "speed".into(),
+                amount: 1.5,
+            },
+            Effect::Pair(1, 2),
+        ] {
+            let value = to_value(&effect).unwrap();
+            assert_eq!(from_value::<Effect>(value).unwrap(), effect);
+        }
+    }
+
+    #[test]
+    fn map_keys_round_trip() {
+        let map =
+            BTreeMap::from([(-1, "a".to_owned()), (2, "b".into())]);
+        let value = to_value(&map).unwrap();
+        assert_eq!(
+            value,
+            Value::Dict(dict! { "-1" => "a", "2" => "b" })
+        );
+        assert_eq!(
+            from_value::<BTreeMap<i32, String>>(value).unwrap(),
+            map
+        );
+
+        let map = BTreeMap::from([(true, 1), (false, 0)]);
+        let value = to_value(&map).unwrap();
+        assert_eq!(
+            from_value::<BTreeMap<bool, i64>>(value).unwrap(),
+            map
+        );
+
+        let map = BTreeMap::from([(Rarity::Rare, 'x')]);
+        let value = to_value(&map).unwrap();
+        assert_eq!(
+            from_value::<BTreeMap<Rarity, char>>(value).unwrap(),
+            map
+        );
+
+        let value = Value::Dict(dict! { "256" => 1 });
+        assert!(from_value::<BTreeMap<u8, i64>>(value).is_err());
+
+        let map = BTreeMap::from([((1, 2), "a")]);
+        assert!(to_value(&map).is_err());
+    }
+
+    #[test]
+    fn numbers_are_converted() {
+        assert_eq!(
+            to_value(&u64::MAX).unwrap(),
+            Value::Float(u64::MAX as f64)
+        );
+        assert_eq!(from_value::<f32>(Value::Int(2)).unwrap(), 2.0);
+        assert_eq!(from_value::<u8>(Value::Int(255)).unwrap(), 255);
+        assert!(from_value::<u8>(Value::Int(256)).is_err());
+        assert!(from_value::<i64>(Value::Float(1.0)).is_err());
+    }
+
+    #[test]
+    fn unsupported_values_are_errors() {
+        let err =
+            from_value::<String>(Value::Length(Default::default()))
+                .unwrap_err();
+        assert_eq!(err.0, "invalid type: length, expected a string");
+    }
+
+    #[test]
+    fn serde_wrapper() {
+        let value = Serde(item()).into_value();
+        let Serde(item) = Serde::<Item>::from_value(value).unwrap();
+        assert_eq!(item.name, "Potion");
+        assert!(Serde::<Item>::from_value(Value::Int(1)).is_err());
+
+        let map = BTreeMap::from([((1, 2), "a")]);
+        assert_eq!(Serde(map).into_value(), Value::None);
+    }
+}
@@ -1,11 +1,26 @@
+use std::ops::ControlFlow;
+
 use bevy::asset::io::Reader;
-use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
-use bevy::platform::collections::HashMap;
+use bevy::asset::{
+    AssetLoader, AssetPath, AsyncReadExt, LoadContext, UntypedAssetId,
+};
+use bevy::ecs::system::SystemParam;
+use bevy::platform::collections::{HashMap, HashSet};
 use bevy::prelude::*;
-use typst::foundations::Module;
-use typst::syntax::{FileId, RootedPath, Source, VirtualPath};
+use ecow::EcoString;
+use typst::foundations::{Bytes, Content, Module};
+use typst::syntax::{
+    FileId, RootedPath, Source, SyntaxNode, VirtualPath, VirtualRoot,
+    ast,
+};
 
+use crate::diag::{DiagnosticStage, VelystDiagnostic};
+use crate::func::{TypstFuncSignatures, VelystContent};
 use crate::world::VelystWorld;
+use crate::world::engine::{
+    EngineItem, VelystEngineUpdated, engine_entrypoint,
+    imported_engine_items,
+};
 
 pub struct TypstAssetPlugin;
 
@@ -13,57 +28,570 @@ impl Plugin for TypstAssetPlugin {
     fn build(&self, app: &mut App) {
         app.init_asset::<VelystSource>()
             .init_asset_loader::<VelystSourceLoader>()
+            .init_asset::<VelystFile>()
+            .init_asset_loader::<VelystFileLoader>()
             .init_resource::<VelystModules>()
-            .add_systems(PreUpdate, eval_source);
+            .init_resource::<VelystEvalErrors>()
+            .init_resource::<VelystDependencies>()
+            .add_message::<VelystModuleUpdated>()
+            .add_systems(PreUpdate, eval_source)
+            .add_systems(Last, untrack_removed_contents);
     }
 }
 
-fn eval_source(
+/// Messages read by [`eval_source`].
+#[derive(SystemParam)]
+pub(crate) struct EvalEvents<'w, 's> {
+    sources: MessageReader<'w, 's, AssetEvent<VelystSource>>,
+    files: MessageReader<'w, 's, AssetEvent<VelystFile>>,
+    engine: MessageReader<'w, 's, VelystEngineUpdated>,
+}
+
+/// Resources written by [`eval_source`].
+#[derive(SystemParam)]
+pub(crate) struct EvalOutputs<'w, 's> {
+    modules: ResMut<'w, VelystModules>,
+    eval_errors: ResMut<'w, VelystEvalErrors>,
+    dependencies: ResMut<'w, VelystDependencies>,
+    contents: Query<'w, 's, &'static mut VelystContent>,
+}
+
+pub(crate) fn eval_source(
     world: VelystWorld,
-    mut evr_asset_event: MessageReader<AssetEvent<VelystSource>>,
-    mut modules: ResMut<VelystModules>,
-    sources: Res<Assets<VelystSource>>,
+    mut events: EvalEvents,
+    mut outputs: EvalOutputs,
+    mut module_updates: MessageWriter<VelystModuleUpdated>,
+    mut diagnostics: MessageWriter<VelystDiagnostic>,
+    signatures: Option<Res<TypstFuncSignatures>>,
 ) {
-    let mut reset = false;
+    let sources = &world.sources;
+    let mut to_eval = Vec::new();
+    let mut changed_files = HashSet::new();
+    // Files that finished loading, which layouts may have been
+    // waiting for.
+    let mut loaded_files = HashSet::new();
 
-    for asset_event in evr_asset_event.read() {
+    for asset_event in events.sources.read() {
         match asset_event {
-            AssetEvent::Added { id }
-            | AssetEvent::Modified { id } => {
-                let Some(source) = sources.get(*id) else {
-                    continue;
-                };
-
-                // Reset the file slots if this is the first
-                // compilation in this frame.
-                if !reset {
-                    let mut file_slots =
-                        world.file_slots.lock().unwrap();
-                    for slot in file_slots.values_mut() {
-                        slot.reset()
-                    }
-                    reset = true;
+            AssetEvent::Added { id } => {
+                if let Some(source) = sources.get(*id) {
+                    loaded_files.insert(source.id());
                 }
-
-                if let Some(module) = world.eval_source(&source.0) {
-                    modules.insert(*id, module);
+                to_eval.push(*id);
+            }
+            AssetEvent::Modified { id } => {
+                if let Some(source) = sources.get(*id) {
+                    changed_files.insert(source.id());
                 }
+                to_eval.push(*id);
             }
             AssetEvent::Removed { id }
             | AssetEvent::Unused { id } => {
-                modules.remove(id);
+                outputs.modules.remove(id);
+                outputs.eval_errors.remove(id);
+                outputs.dependencies.untrack(id);
+            }
+            // Readiness of whole module trees is checked by
+            // `check_source_ready`, which can't rely on
+            // `LoadedWithDependencies`.
+            _ => {}
+        }
+    }
+
+    for file_event in events.files.read() {
+        match file_event {
+            AssetEvent::Added { id } => {
+                if let Some(file) = outputs.dependencies.file_of(*id)
+                {
+                    loaded_files.insert(file);
+                }
+            }
+            AssetEvent::Modified { id } => {
+                if let Some(file) = outputs.dependencies.file_of(*id)
+                {
+                    changed_files.insert(file);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut engine_items = events
+        .engine
+        .read()
+        .map(|update| update.item)
+        .collect::<HashSet<_>>();
+
+    // Contents that read a changed engine item during layout need to
+    // be laid out again.
+    for entity in
+        outputs.dependencies.layout_engine_dependents(&engine_items)
+    {
+        if let Ok(mut content) = outputs.contents.get_mut(entity) {
+            content.set_changed();
+        }
+    }
+
+    // Modules importing a changed engine item are treated as changed
+    // files, so that modules importing them are re-evaluated too.
+    // Time changes every frame, so it is only read during layout.
+    engine_items.remove(&EngineItem::Time);
+    for id in outputs.dependencies.engine_dependents(&engine_items) {
+        if let Some(source) = sources.get(id) {
+            changed_files.insert(source.id());
+        }
+        to_eval.push(id);
+    }
+
+    // Modules that imported a changed file need to be re-evaluated
+    // as well.
+    to_eval.extend(outputs.dependencies.dependents(&changed_files));
+
+    // Contents that read a changed or newly loaded file during
+    // layout, e.g. in a function body, need to be laid out again.
+    loaded_files.extend(changed_files.iter().copied());
+    for entity in
+        outputs.dependencies.layout_dependents(&loaded_files)
+    {
+        if let Ok(mut content) = outputs.contents.get_mut(entity) {
+            content.set_changed();
+        }
+    }
+
+    // Modules can read `sys.inputs` from the library, so re-evaluate
+    // all of them when it changes.
+    if world.library.is_changed() && !world.library.is_added() {
+        to_eval.extend(outputs.modules.keys().copied());
+        to_eval.extend(outputs.eval_errors.keys().copied());
+    }
+
+    let mut evaluated = HashSet::new();
+    for id in to_eval {
+        if !evaluated.insert(id) {
+            continue;
+        }
+
+        let Some(source) = sources.get(id) else {
+            continue;
+        };
+
+        // Reset the file slots so that only the files read by this
+        // module are marked as accessed.
+        world.reset_file_slots();
+
+        let result = world.eval_source(source);
+        outputs.dependencies.track(
+            id,
+            world
+                .accessed_files()
+                .into_iter()
+                .filter(|file| *file != source.id()),
+            &world,
+        );
+        outputs
+            .dependencies
+            .track_engine(id, imported_engine_items(source));
+
+        let mut diags = result
+            .diagnostics
+            .iter()
+            .map(|diag| {
+                VelystDiagnostic::from_source(
+                    &world,
+                    DiagnosticStage::Eval,
+                    diag,
+                )
+                .with_source(id)
+            })
+            .collect::<Vec<_>>();
+
+        match result.output {
+            Some(module) => {
+                // Warn about registered functions whose arguments do
+                // not match their Typst definition.
+                if let Some(signatures) = &signatures
+                    && let Some(path) =
+                        world.asset_server.get_path(id)
+                {
+                    diags.extend(
+                        signatures
+                            .check(&path, &module, &world)
+                            .iter()
+                            .map(|diag| {
+                                VelystDiagnostic::from_source(
+                                    &world,
+                                    DiagnosticStage::Eval,
+                                    diag,
+                                )
+                                .with_source(id)
+                            }),
+                    );
+                }
+
+                outputs.modules.insert(id, module);
+                outputs.eval_errors.remove(&id);
+                module_updates.write(VelystModuleUpdated { id });
+            }
+            None => {
+                error!("Evaluation failed for {:?}!", source.id());
+                outputs.eval_errors.insert(
+                    id,
+                    diags
+                        .iter()
+                        .filter(|diag| diag.is_error())
+                        .cloned()
+                        .collect(),
+                );
             }
-            AssetEvent::LoadedWithDependencies { .. } => {}
         }
+
+        diagnostics.write_batch(diags);
     }
 }
 
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct VelystModules(HashMap<AssetId<VelystSource>, Module>);
 
+/// Sent whenever a [`VelystSource`] is (re-)evaluated into a new
+/// module in [`VelystModules`].
+#[derive(Message, Debug, Clone, Copy)]
+pub struct VelystModuleUpdated {
+    pub id: AssetId<VelystSource>,
+}
+
+/// Tracks the files each [`VelystSource`] read during its last
+/// evaluation, so that it can be re-evaluated when any of them
+/// change.
+///
+/// Function bodies only run when their content is laid out, so the
+/// files read during the last layout of each entity are tracked as
+/// well, to lay it out again when any of them change.
+#[derive(Resource, Default)]
+pub struct VelystDependencies {
+    /// Files read by each module, excluding the module itself.
+    files: HashMap<AssetId<VelystSource>, HashSet<FileId>>,
+    /// Files read while laying out the content of each entity.
+    layout: HashMap<Entity, HashSet<FileId>>,
+    /// Handles that keep dependency files loaded so that Bevy
+    /// reports their modifications.
+    handles: HashMap<FileId, UntypedHandle>,
+    /// Items of the `@velyst/engine` package imported by each
+    /// module.
+    engine: HashMap<AssetId<VelystSource>, HashSet<EngineItem>>,
+    /// Items of the `@velyst/engine` package read while laying out
+    /// the content of each entity.
+    layout_engine: HashMap<Entity, HashSet<EngineItem>>,
+}
+
+impl VelystDependencies {
+    /// Files read by the given module during its last evaluation.
+    pub fn files(
+        &self,
+        id: &AssetId<VelystSource>,
+    ) -> Option<&HashSet<FileId>> {
+        self.files.get(id)
+    }
+
+    /// Files read by the given entity during its last layout.
+    pub fn layout_files(
+        &self,
+        entity: Entity,
+    ) -> Option<&HashSet<FileId>> {
+        self.layout.get(&entity)
+    }
+
+    /// Modules that read any of the given files.
+    pub fn dependents(
+        &self,
+        changed: &HashSet<FileId>,
+    ) -> Vec<AssetId<VelystSource>> {
+        self.files
+            .iter()
+            .filter(|(_, files)| !files.is_disjoint(changed))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Entities that read any of the given files during layout.
+    pub fn layout_dependents(
+        &self,
+        changed: &HashSet<FileId>,
+    ) -> Vec<Entity> {
+        self.layout
+            .iter()
+            .filter(|(_, files)| !files.is_disjoint(changed))
+            .map(|(entity, _)| *entity)
+            .collect()
+    }
+
+    /// Entities that read any of the given engine items during
+    /// layout.
+    pub fn layout_engine_dependents(
+        &self,
+        changed: &HashSet<EngineItem>,
+    ) -> Vec<Entity> {
+        self.layout_engine
+            .iter()
+            .filter(|(_, items)| !items.is_disjoint(changed))
+            .map(|(entity, _)| *entity)
+            .collect()
+    }
+
+    /// Modules that import any of the given engine items.
+    pub fn engine_dependents(
+        &self,
+        changed: &HashSet<EngineItem>,
+    ) -> Vec<AssetId<VelystSource>> {
+        self.engine
+            .iter()
+            .filter(|(_, items)| !items.is_disjoint(changed))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// The file watched through the given asset.
+    fn file_of(
+        &self,
+        id: impl Into<UntypedAssetId>,
+    ) -> Option<FileId> {
+        let id = id.into();
+        self.handles
+            .iter()
+            .find(|(_, handle)| handle.id() == id)
+            .map(|(file, _)| *file)
+    }
+
+    /// Record the files read by a module and start watching them.
+    fn track(
+        &mut self,
+        id: AssetId<VelystSource>,
+        files: impl IntoIterator<Item = FileId>,
+        world: &VelystWorld,
+    ) {
+        let files = files.into_iter().collect::<HashSet<_>>();
+        self.watch(&files, world);
+        self.files.insert(id, files);
+        self.retain_handles();
+    }
+
+    /// Record the files and engine items read while laying out the
+    /// content of an entity and start watching the files.
+    pub(crate) fn track_layout(
+        &mut self,
+        entity: Entity,
+        content: &Content,
+        files: impl IntoIterator<Item = FileId>,
+        world: &VelystWorld,
+    ) {
+        let files = files.into_iter().collect::<HashSet<_>>();
+        let items = self.layout_engine_items(content, &files, world);
+        if items.is_empty() {
+            self.layout_engine.remove(&entity);
+        } else {
+            self.layout_engine.insert(entity, items);
+        }
+
+        if self.layout.get(&entity) == Some(&files) {
+            return;
+        }
+
+        self.watch(&files, world);
+        if files.is_empty() {
+            self.layout.remove(&entity);
+        } else {
+            self.layout.insert(entity, files);
+        }
+        self.retain_handles();
+    }
+
+    /// Engine items that may have been read while laying out
+    /// `content`, given the files read along the way.
+    ///
+    /// Layout runs code of the files the content was created in,
+    /// which in turn may call into the files they imported. The items
+    /// imported by any of them count as read. Falls back to every
+    /// item if none are found, e.g. for content built in Rust.
+    fn layout_engine_items(
+        &self,
+        content: &Content,
+        files: &HashSet<FileId>,
+        world: &VelystWorld,
+    ) -> HashSet<EngineItem> {
+        if !files.contains(&engine_entrypoint()) {
+            return HashSet::new();
+        }
+
+        let mut origins = files.clone();
+        let _ = content.traverse(&mut |elem| -> ControlFlow<()> {
+            origins.extend(elem.span().id());
+            ControlFlow::Continue(())
+        });
+        // Imported files are read when evaluating a module, not when
+        // calling into it during layout.
+        let imported = self
+            .files
+            .iter()
+            .filter(|(id, _)| {
+                world.sources.get(**id).is_some_and(|source| {
+                    origins.contains(&source.id())
+                })
+            })
+            .flat_map(|(_, files)| files.iter().copied())
+            .collect::<Vec<_>>();
+        origins.extend(imported);
+
+        let items = origins
+            .into_iter()
+            .filter_map(|file| typst::World::source(world, file).ok())
+            .flat_map(|source| imported_engine_items(&source))
+            .collect::<HashSet<_>>();
+        if items.is_empty() {
+            EngineItem::ALL.into_iter().collect()
+        } else {
+            items
+        }
+    }
+
+    /// Start watching files that are not watched yet.
+    fn watch(
+        &mut self,
+        files: &HashSet<FileId>,
+        world: &VelystWorld,
+    ) {
+        for file in files.iter() {
+            if self.handles.contains_key(file) {
+                continue;
+            }
+            if let Some(handle) = watch_file(*file, world) {
+                self.handles.insert(*file, handle);
+            }
+        }
+    }
+
+    /// Record the engine items imported by a module.
+    fn track_engine(
+        &mut self,
+        id: AssetId<VelystSource>,
+        items: HashSet<EngineItem>,
+    ) {
+        if items.is_empty() {
+            self.engine.remove(&id);
+        } else {
+            self.engine.insert(id, items);
+        }
+    }
+
+    /// Forget the files read by a module.
+    fn untrack(&mut self, id: &AssetId<VelystSource>) {
+        self.engine.remove(id);
+        if self.files.remove(id).is_some() {
+            self.retain_handles();
+        }
+    }
+
+    /// Forget the files read while laying out an entity.
+    fn untrack_layout(&mut self, entity: Entity) {
+        self.layout_engine.remove(&entity);
+        if self.layout.remove(&entity).is_some() {
+            self.retain_handles();
+        }
+    }
+
+    /// Drop handles of files that no module or entity depends on
+    /// anymore.
+    fn retain_handles(&mut self) {
+        let Self {
+            files,
+            layout,
+            handles,
+            ..
+        } = self;
+        handles.retain(|file, _| {
+            files
+                .values()
+                .chain(layout.values())
+                .any(|files| files.contains(file))
+        });
+    }
+}
+
+/// Forget the layout files of entities whose content was removed.
+fn untrack_removed_contents(
+    mut removed: RemovedComponents<VelystContent>,
+    mut dependencies: ResMut<VelystDependencies>,
+) {
+    for entity in removed.read() {
+        dependencies.untrack_layout(entity);
+    }
+}
+
+/// Load a project file as an asset so that Bevy watches it for
+/// changes. Package files are immutable and never watched.
+fn watch_file(
+    file: FileId,
+    world: &VelystWorld,
+) -> Option<UntypedHandle> {
+    if !matches!(file.root(), VirtualRoot::Project) {
+        return None;
+    }
+
+    let path = world.asset_path(file);
+    match file.vpath().extension() {
+        Some("typ") => Some(
+            world.asset_server.load::<VelystSource>(path).untyped(),
+        ),
+        _ => Some(
+            world.asset_server.load::<VelystFile>(path).untyped(),
+        ),
+    }
+}
+
+/// Errors from the last failed evaluation of each [`VelystSource`].
+///
+/// Cleared once the source evaluates successfully again.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct VelystEvalErrors(
+    HashMap<AssetId<VelystSource>, Vec<VelystDiagnostic>>,
+);
+
 /// A Typst [`Source`] file loaded from disk.
 #[derive(Asset, TypePath, Deref)]
-pub struct VelystSource(pub(super) Source);
+pub struct VelystSource {
+    #[deref]
+    pub(super) source: Source,
+    /// Typst modules imported or included by this source, loaded as
+    /// asset dependencies.
+    #[dependency]
+    imports: Vec<Handle<VelystSource>>,
+    /// Other files read by this source, loaded as asset
+    /// dependencies.
+    #[dependency]
+    data: Vec<Handle<VelystFile>>,
+    /// Project files referenced by this source through string paths.
+    files: Vec<FileId>,
+}
+
+impl VelystSource {
+    /// Handles of the Typst modules imported or included by this
+    /// source.
+    pub fn imports(&self) -> &[Handle<VelystSource>] {
+        &self.imports
+    }
+
+    /// Handles of the other files read by this source, e.g. through
+    /// `image()` or `json()`.
+    pub fn data(&self) -> &[Handle<VelystFile>] {
+        &self.data
+    }
+
+    /// Project files referenced by this source, e.g. through
+    /// `#import`, `#include`, `read()` or `image()`.
+    ///
+    /// Package imports are not included.
+    pub fn files(&self) -> &[FileId] {
+        &self.files
+    }
+}
 
 #[derive(Default, TypePath)]
 pub struct VelystSourceLoader;
@@ -84,21 +612,64 @@ impl AssetLoader for VelystSourceLoader {
         let mut text = String::new();
         reader.read_to_string(&mut text).await?;
 
-        let path = load_context.path().to_string();
+        let path = load_context.path().path().to_string_lossy();
+        let vpath = VirtualPath::new(&path).map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+        })?;
         let source = Source::new(
             FileId::new(RootedPath::new(
-                typst::syntax::VirtualRoot::Project,
-                VirtualPath::new(&path).map_err(|e| {
-                    std::io::Error::new(
-                        std::io::ErrorKind::InvalidInput,
-                        e,
-                    )
-                })?,
+                VirtualRoot::Project,
+                vpath.clone(),
             )),
             text,
         );
 
-        Ok(VelystSource(source))
+        let mut paths = Vec::new();
+        collect_paths(source.root(), &mut paths);
+
+        let mut imports = Vec::new();
+        let mut data = Vec::new();
+        let mut files = Vec::new();
+        for path in paths {
+            // Package files are resolved by the world itself.
+            if path.starts_with('@') {
+                continue;
+            }
+
+            let Some(file_path) = vpath
+                .parent()
+                .and_then(|parent| parent.join(&path).ok())
+            else {
+                warn!("Unable to resolve {path:?} from {vpath:?}.");
+                continue;
+            };
+
+            let file = FileId::new(RootedPath::new(
+                VirtualRoot::Project,
+                file_path.clone(),
+            ));
+            if files.contains(&file) {
+                continue;
+            }
+            files.push(file);
+
+            let asset_path = AssetPath::from_path_buf(
+                file_path.get_without_slash().into(),
+            )
+            .with_source(load_context.path().source().clone_owned());
+            if file_path.extension() == Some("typ") {
+                imports.push(load_context.load(asset_path));
+            } else {
+                data.push(load_context.load(asset_path));
+            }
+        }
+
+        Ok(VelystSource {
+            source,
+            imports,
+            data,
+            files,
+        })
     }
 
     fn extensions(&self) -> &[&str] {
@@ -106,32 +677,200 @@ impl AssetLoader for VelystSourceLoader {
     }
 }
 
-// /// An arbitrary file required by Typst compiler,
-// /// stored in [`Bytes`] format.
-// #[derive(Asset, TypePath, Deref)]
-// pub struct TypstFile(Bytes);
+/// Typst functions whose first positional argument is a path to a
+/// project file.
+const PATH_FUNCS: &[&str] = &[
+    "read",
+    "image",
+    "json",
+    "csv",
+    "yaml",
+    "toml",
+    "xml",
+    "cbor",
+    "bibliography",
+    "plugin",
+];
+
+/// Collect the string paths of `#import`, `#include` and path based
+/// function calls, e.g. `read("data.txt")`, in a syntax tree.
+fn collect_paths(node: &SyntaxNode, paths: &mut Vec<EcoString>) {
+    let path = if let Some(import) = node.cast::<ast::ModuleImport>()
+    {
+        string_path(import.source())
+    } else if let Some(include) = node.cast::<ast::ModuleInclude>() {
+        string_path(include.source())
+    } else if let Some(call) = node.cast::<ast::FuncCall>() {
+        match call.callee() {
+            ast::Expr::Ident(ident)
+                if PATH_FUNCS.contains(&ident.get().as_str()) =>
+            {
+                call.args().items().find_map(|arg| match arg {
+                    ast::Arg::Pos(expr) => string_path(expr),
+                    _ => None,
+                })
+            }
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    paths.extend(path);
+
+    for child in node.children() {
+        collect_paths(child, paths);
+    }
+}
+
+/// Get the path of a string literal expression.
+fn string_path(expr: ast::Expr) -> Option<EcoString> {
+    match expr {
+        ast::Expr::Str(str) => Some(str.get()),
+        _ => None,
+    }
+}
+
+/// An arbitrary file read by Typst, e.g. through `image()`, `json()`,
+/// `csv()`, `read()` or `bibliography()`, stored in [`Bytes`] format.
+///
+/// Loaded to make Bevy track and hot-reload the file, and to serve
+/// its contents to [`VelystWorld`] without blocking.
+#[derive(Asset, TypePath, Deref)]
+pub struct VelystFile(Bytes);
+
+/// Loads any file as a [`VelystFile`].
+///
+/// Has no extensions, so it is only used when a [`VelystFile`] is
+/// requested explicitly.
+#[derive(Default, TypePath)]
+pub struct VelystFileLoader;
+
+impl AssetLoader for VelystFileLoader {
+    type Asset = VelystFile;
+
+    type Settings = ();
+
+    type Error = std::io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        Ok(VelystFile(Bytes::new(bytes)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::func::DynamicVelystFunc;
+    use crate::renderer::WorldScene;
+    use crate::test_utils::{
+        frame_text, test_app, update_until, write_file,
+    };
 
-// #[derive(Default)]
-// pub struct TypstFileLoader;
+    #[test]
+    fn relayout_when_file_read_in_function_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "main.typ",
+            "#let card(path) = include path",
+        );
+        write_file(dir.path(), "part.typ", "Old");
 
-// impl AssetLoader for TypstFileLoader {
-//     type Asset = TypstFile;
+        let mut app = test_app(dir.path());
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let id = handle.id();
+        let entity = app
+            .world_mut()
+            .spawn((
+                DynamicVelystFunc::new(handle, "card")
+                    .with_positional("part.typ"),
+                WorldScene::default(),
+                Visibility::default(),
+            ))
+            .id();
 
-//     type Settings = ();
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "Old"
+        }));
 
-//     type Error = std::io::Error;
+        // The path is only known to the function body, at layout.
+        let part = FileId::new(RootedPath::new(
+            VirtualRoot::Project,
+            VirtualPath::new("/part.typ").unwrap(),
+        ));
+        let dependencies =
+            app.world().resource::<VelystDependencies>();
+        assert!(!dependencies.files(&id).unwrap().contains(&part));
+        assert!(
+            dependencies
+                .layout_files(entity)
+                .unwrap()
+                .contains(&part)
+        );
+
+        write_file(dir.path(), "part.typ", "New");
+        app.world().resource::<AssetServer>().reload("part.typ");
+
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "New"
+        }));
+    }
+
+    #[test]
+    fn reload_data_read_in_function() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "main.typ",
+            "#let stat(path) = read(path)",
+        );
+        write_file(dir.path(), "stats.txt", "10");
+
+        let mut app = test_app(dir.path());
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let entity = app
+            .world_mut()
+            .spawn((
+                DynamicVelystFunc::new(handle, "stat")
+                    .with_positional("stats.txt"),
+                WorldScene::default(),
+                Visibility::default(),
+            ))
+            .id();
+
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "10"
+        }));
 
-//     async fn load(
-//         &self,
-//         reader: &mut dyn Reader,
-//         _settings: &Self::Settings,
-//         _load_context: &mut LoadContext<'_>,
-//     ) -> Result<Self::Asset, Self::Error> {
-//         let mut bytes = Vec::new();
-//         reader.read_to_end(&mut bytes).await?;
+        // The data file is watched even though no module reads it.
+        let stats = FileId::new(RootedPath::new(
+            VirtualRoot::Project,
+            VirtualPath::new("/stats.txt").unwrap(),
+        ));
+        let dependencies =
+            app.world().resource::<VelystDependencies>();
+        assert!(dependencies.handles.contains_key(&stats));
 
-//         let source = Bytes::new(bytes);
+        write_file(dir.path(), "stats.txt", "20");
+        app.world().resource::<AssetServer>().reload("stats.txt");
 
-//         Ok(TypstFile(source))
-//     }
-// }
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "20"
+        }));
+    }
+}
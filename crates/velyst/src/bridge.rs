@@ -0,0 +1,426 @@
+//! Conversions between Bevy and Typst types.
+//!
+//! One logical pixel maps to one Typst point, just like the layout of
+//! [`UiScene`][crate::renderer::UiScene] nodes. Colors are converted
+//! through sRGB, and images become `image` elements of their raw
+//! pixels.
+//!
+//! # Example
+//!
+//! ```
+//! use bevy::prelude::*;
+//! use velyst::prelude::*;
+//!
+//! let fill = Color::srgb_u8(0xA9, 0xDC, 0x76).typst_color();
+//! assert_eq!(fill.to_hex(), "#a9dc76");
+//!
+//! let size = Vec2::new(120.0, 40.0).typst_axes();
+//! assert_eq!(size.x, Abs::pt(120.0).length());
+//!
+//! let width = Val::Px(80.0).typst_rel();
+//! assert_eq!(width, Some(Abs::pt(80.0).rel()));
+//! ```
+
+use std::any::TypeId;
+
+use bevy::prelude::*;
+use bevy::reflect::{GetTypeRegistration, TypeRegistry};
+use bevy::render::render_resource::TextureFormat;
+use typst::foundations::{
+    Bytes, Content, Derived, Dict, IntoValue, NativeElement, Smart,
+    Value, dict,
+};
+use typst::layout::{Axes, Length, Ratio, Rel};
+use typst::loading::{DataSource, LoadSource, Loaded};
+use typst::syntax::Spanned;
+use typst::visualize as viz;
+use typst_element::prelude::UnitExt;
+
+use crate::func::ReflectTypstValue;
+
+/// Registers [`ReflectTypstValue`] for the Bevy types in this module,
+/// so that they are converted with the functions here by
+/// [`reflect_to_value`][crate::func::reflect_to_value].
+pub struct VelystBridgePlugin;
+
+impl Plugin for VelystBridgePlugin {
+    fn build(&self, app: &mut App) {
+        let registry = app.world().resource::<AppTypeRegistry>();
+        let mut registry = registry.write();
+
+        register::<Color>(&mut registry, |value| {
+            value
+                .try_downcast_ref::<Color>()
+                .map(|color| color.typst_color().into_value())
+        });
+        register::<Vec2>(&mut registry, |value| {
+            value
+                .try_downcast_ref::<Vec2>()
+                .map(|vec| vec.typst_axes().into_value())
+        });
+        register::<Rect>(&mut registry, |value| {
+            value
+                .try_downcast_ref::<Rect>()
+                .map(|rect| rect.typst_dict().into_value())
+        });
+        register::<Val>(&mut registry, |value| {
+            value
+                .try_downcast_ref::<Val>()
+                .map(|val| val.typst_value())
+        });
+    }
+}
+
+/// Register a type with a [`ReflectTypstValue`] of the given
+/// conversion.
+fn register<T: GetTypeRegistration>(
+    registry: &mut TypeRegistry,
+    into_value: fn(&dyn PartialReflect) -> Option<Value>,
+) {
+    registry.register::<T>();
+    if let Some(registration) = registry.get_mut(TypeId::of::<T>()) {
+        registration.insert(ReflectTypstValue::new(into_value));
+    }
+}
+
+pub trait BevyColorExt {
+    /// Convert into a Typst RGB color.
+    fn typst_color(&self) -> viz::Color;
+}
+
+impl BevyColorExt for Color {
+    fn typst_color(&self) -> viz::Color {
+        let Srgba {
+            red,
+            green,
+            blue,
+            alpha,
+        } = self.to_srgba();
+        viz::Rgb::new(red, green, blue, alpha).into()
+    }
+}
+
+pub trait TypstColorExt {
+    /// Convert into a Bevy sRGB color.
+    fn bevy_color(&self) -> Color;
+}
+
+impl TypstColorExt for viz::Color {
+    fn bevy_color(&self) -> Color {
+        let rgb = self.to_rgb();
+        Color::srgba(rgb.red, rgb.green, rgb.blue, rgb.alpha)
+    }
+}
+
+pub trait Vec2Ext {
+    /// Convert into Typst lengths, in points.
+    fn typst_axes(self) -> Axes<Length>;
+}
+
+impl Vec2Ext for Vec2 {
+    fn typst_axes(self) -> Axes<Length> {
+        Axes::new(self.x.length(), self.y.length())
+    }
+}
+
+pub trait ValExt {
+    /// Convert into a Typst relative length, with [`Val::Percent`]
+    /// relative to the parent in Typst.
+    ///
+    /// `None` for [`Val::Auto`] and viewport units, which Typst
+    /// cannot express. Use [`Self::typst_smart_rel`] to resolve them.
+    fn typst_rel(self) -> Option<Rel<Length>>;
+
+    /// Convert into a Typst relative length, resolving viewport
+    /// units against `viewport_size` in logical pixels.
+    ///
+    /// [`Val::Auto`] becomes [`Smart::Auto`].
+    fn typst_smart_rel(
+        self,
+        viewport_size: Vec2,
+    ) -> Smart<Rel<Length>>;
+
+    /// Convert into a Typst value: `auto`, a length or a ratio.
+    ///
+    /// Viewport units become a single-entry dictionary of their unit
+    /// and ratio, e.g. `(vw: 50%)`, to be resolved in Typst.
+    fn typst_value(self) -> Value;
+}
+
+impl ValExt for Val {
+    fn typst_rel(self) -> Option<Rel<Length>> {
+        match self {
+            Val::Px(value) => Some(value.rel()),
+            Val::Percent(value) => Some(ratio(value).into()),
+            Val::Auto
+            | Val::Vw(_)
+            | Val::Vh(_)
+            | Val::VMin(_)
+            | Val::VMax(_) => None,
+        }
+    }
+
+    fn typst_smart_rel(
+        self,
+        viewport_size: Vec2,
+    ) -> Smart<Rel<Length>> {
+        let px = |value: f32| value.smart_rel();
+        match self {
+            Val::Auto => Smart::Auto,
+            Val::Px(_) | Val::Percent(_) => {
+                Smart::from(self.typst_rel())
+            }
+            Val::Vw(value) => px(viewport_size.x * value / 100.0),
+            Val::Vh(value) => px(viewport_size.y * value / 100.0),
+            Val::VMin(value) => {
+                px(viewport_size.min_element() * value / 100.0)
+            }
+            Val::VMax(value) => {
+                px(viewport_size.max_element() * value / 100.0)
+            }
+        }
+    }
+
+    fn typst_value(self) -> Value {
+        let (unit, value) = match self {
+            Val::Auto => return Value::Auto,
+            Val::Px(value) => return value.length().into_value(),
+            Val::Percent(value) => return ratio(value).into_value(),
+            Val::Vw(value) => ("vw", value),
+            Val::Vh(value) => ("vh", value),
+            Val::VMin(value) => ("vmin", value),
+            Val::VMax(value) => ("vmax", value),
+        };
+        Value::Dict(dict! { unit => ratio(value) })
+    }
+}
+
+/// A ratio from a percentage.
+fn ratio(percent: f32) -> Ratio {
+    Ratio::new(percent as f64 / 100.0)
+}
+
+pub trait RectExt {
+    /// Convert into a dictionary with the `x`, `y`, `width` and
+    /// `height` of the rectangle as lengths, in points.
+    ///
+    /// Inverted corners are swapped, so that the size is never
+    /// negative.
+    fn typst_dict(&self) -> Dict;
+}
+
+impl RectExt for Rect {
+    fn typst_dict(&self) -> Dict {
+        let rect = Rect::from_corners(self.min, self.max);
+        dict! {
+            "x" => rect.min.x.length(),
+            "y" => rect.min.y.length(),
+            "width" => rect.width().length(),
+            "height" => rect.height().length(),
+        }
+    }
+}
+
+pub trait ImageExt {
+    /// Convert into a Typst `image` element of the raw pixels, where
+    /// one pixel maps to one point.
+    ///
+    /// Linear formats are encoded to sRGB first, since Typst treats
+    /// 8-bit pixels as sRGB. `None` if the image has no data on the
+    /// CPU or a format other than 8-bit RGBA or single channel.
+    fn typst_image(&self) -> Option<Content>;
+}
+
+impl ImageExt for Image {
+    fn typst_image(&self) -> Option<Content> {
+        // The encoding, whether the last channel is alpha, and whether
+        // the color channels are linear.
+        let (encoding, alpha, linear) =
+            match self.texture_descriptor.format {
+                TextureFormat::Rgba8UnormSrgb => {
+                    (viz::PixelEncoding::Rgba8, true, false)
+                }
+                TextureFormat::Rgba8Unorm => {
+                    (viz::PixelEncoding::Rgba8, true, true)
+                }
+                TextureFormat::R8Unorm => {
+                    (viz::PixelEncoding::Luma8, false, true)
+                }
+                _ => return None,
+            };
+        let format = viz::PixelFormat {
+            encoding,
+            width: self.width(),
+            height: self.height(),
+        };
+
+        let mut data = self.data.clone()?;
+        if linear {
+            for (i, byte) in data.iter_mut().enumerate() {
+                // Alpha stays linear.
+                if !(alpha && i % 4 == 3) {
+                    *byte = linear_to_srgb(*byte);
+                }
+            }
+        }
+        let data = Bytes::new(data);
+        let loaded = Loaded::new(
+            Spanned::detached(LoadSource::Bytes),
+            data.clone(),
+        );
+        Some(
+            viz::ImageElem::new(Derived::new(
+                DataSource::Bytes(data),
+                loaded,
+            ))
+            .with_format(Smart::Custom(viz::ImageFormat::Raster(
+                viz::RasterFormat::Pixel(format),
+            )))
+            .pack(),
+        )
+    }
+}
+
+/// Encode a linear 8-bit channel with the sRGB transfer function.
+fn linear_to_srgb(byte: u8) -> u8 {
+    let linear = byte as f32 / u8::MAX as f32;
+    (Srgba::gamma_function_inverse(linear) * u8::MAX as f32).round()
+        as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::RenderAssetUsages;
+    use bevy::render::render_resource::{Extent3d, TextureDimension};
+    use typst::layout::Abs;
+
+    use super::*;
+
+    #[test]
+    fn color_round_trip() {
+        let color = Color::srgba(0.2, 0.4, 0.6, 0.5);
+        let typst_color = color.typst_color();
+        assert_eq!(typst_color.to_hex(), "#33669980");
+
+        let Srgba {
+            red,
+            green,
+            blue,
+            alpha,
+        } = typst_color.bevy_color().to_srgba();
+        for (a, b) in
+            [(red, 0.2), (green, 0.4), (blue, 0.6), (alpha, 0.5)]
+        {
+            assert!((a - b).abs() < 1e-6, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn val_conversions() {
+        let pt = |value: f64| Abs::pt(value).rel();
+        let viewport = Vec2::new(200.0, 100.0);
+
+        assert_eq!(Val::Auto.typst_rel(), None);
+        assert_eq!(Val::Auto.typst_smart_rel(viewport), Smart::Auto);
+        assert_eq!(Val::Auto.typst_value(), Value::Auto);
+
+        assert_eq!(Val::Px(12.0).typst_rel(), Some(pt(12.0)));
+        assert_eq!(
+            Val::Px(12.0).typst_value(),
+            Value::Length(Abs::pt(12.0).into())
+        );
+
+        let half = Rel::from(Ratio::new(0.5));
+        assert_eq!(Val::Percent(50.0).typst_rel(), Some(half));
+        assert_eq!(
+            Val::Percent(50.0).typst_smart_rel(viewport),
+            Smart::Custom(half)
+        );
+        assert_eq!(
+            Val::Percent(50.0).typst_value(),
+            Value::Ratio(Ratio::new(0.5))
+        );
+
+        for (val, unit, resolved) in [
+            (Val::Vw(50.0), "vw", 100.0),
+            (Val::Vh(50.0), "vh", 50.0),
+            (Val::VMin(50.0), "vmin", 50.0),
+            (Val::VMax(50.0), "vmax", 100.0),
+        ] {
+            assert_eq!(val.typst_rel(), None);
+            assert_eq!(
+                val.typst_smart_rel(viewport),
+                Smart::Custom(pt(resolved))
+            );
+            assert_eq!(
+                val.typst_value(),
+                Value::Dict(dict! { unit => Ratio::new(0.5) })
+            );
+        }
+    }
+
+    #[test]
+    fn rect_with_negative_and_inverted_corners() {
+        let rect = Rect {
+            min: Vec2::new(10.0, 0.0),
+            max: Vec2::new(-5.0, -20.0),
+        };
+        assert_eq!(
+            rect.typst_dict(),
+            dict! {
+                "x" => Abs::pt(-5.0).length(),
+                "y" => Abs::pt(-20.0).length(),
+                "width" => Abs::pt(15.0).length(),
+                "height" => Abs::pt(20.0).length(),
+            }
+        );
+    }
+
+    #[test]
+    fn unit_ext_numbers_are_points() {
+        assert_eq!(1.5_f32.length(), Abs::pt(1.5).length());
+        assert_eq!(1.5_f64.rel(), Abs::pt(1.5).rel());
+    }
+
+    #[test]
+    fn image_as_pixels() {
+        let image = Image::new_fill(
+            Extent3d {
+                width: 2,
+                height: 3,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[255, 0, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        let content = image.typst_image().unwrap();
+        let elem = content.to_packed::<viz::ImageElem>().unwrap();
+        assert_eq!(elem.source.derived.data.len(), 2 * 3 * 4);
+        assert_eq!(
+            elem.format.get_cloned(Default::default()),
+            Smart::Custom(viz::ImageFormat::Raster(
+                viz::RasterFormat::Pixel(viz::PixelFormat {
+                    encoding: viz::PixelEncoding::Rgba8,
+                    width: 2,
+                    height: 3,
+                })
+            ))
+        );
+
+        // Linear pixels are encoded to sRGB, except for their alpha.
+        let mut image = image;
+        image.texture_descriptor.format = TextureFormat::Rgba8Unorm;
+        image.data = Some(vec![0, 55, 255, 55]);
+        let content = image.typst_image().unwrap();
+        let elem = content.to_packed::<viz::ImageElem>().unwrap();
+        assert_eq!(
+            elem.source.derived.data.as_slice(),
+            [0, 128, 255, 55]
+        );
+
+        image.texture_descriptor.format = TextureFormat::Rgba16Float;
+        assert!(image.typst_image().is_none());
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bevy::prelude::*;
+use ecow::{EcoString, eco_format};
+use typst::diag::{Severity, SourceDiagnostic};
+use typst::syntax::{DiagSpan, FileId, VirtualRoot};
+use typst::{World, WorldExt};
+
+use crate::asset::VelystSource;
+use crate::world::VelystWorld;
+
+pub struct VelystDiagnosticPlugin;
+
+impl Plugin for VelystDiagnosticPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<VelystDiagnostic>()
+            .add_systems(Last, log_diagnostics);
+    }
+}
+
+/// Log every [`VelystDiagnostic`] emitted this frame.
+fn log_diagnostics(mut diagnostics: MessageReader<VelystDiagnostic>) {
+    for diagnostic in diagnostics.read() {
+        match diagnostic.severity {
+            Severity::Error => error!("{diagnostic}"),
+            Severity::Warning => warn!("{diagnostic}"),
+        }
+    }
+}
+
+/// A structured Typst diagnostic produced while evaluating a
+/// [`VelystSource`] or compiling and laying out a
+/// [`VelystFunc`][crate::func::VelystFunc].
+#[derive(Message, Debug, Clone, PartialEq)]
+pub struct VelystDiagnostic {
+    /// The stage that produced this diagnostic.
+    pub stage: DiagnosticStage,
+    /// The source asset being evaluated, if any.
+    pub source: Option<AssetId<VelystSource>>,
+    /// The entity being compiled or laid out, if any.
+    pub entity: Option<Entity>,
+    /// Whether the diagnostic is an error or a warning.
+    pub severity: Severity,
+    /// The diagnostic message.
+    pub message: EcoString,
+    /// Additional hints to the user.
+    pub hints: Vec<EcoString>,
+    /// The trace of function calls leading to the problem.
+    pub trace: Vec<DiagnosticTrace>,
+    /// The file the diagnostic points into, if any.
+    pub file: Option<FileId>,
+    /// The line and column range within [`Self::file`], if
+    /// resolvable.
+    pub range: Option<LineColumnRange>,
+}
+
+impl VelystDiagnostic {
+    /// Create a diagnostic from a [`SourceDiagnostic`], resolving its
+    /// spans with the given world.
+    pub fn from_source(
+        world: &VelystWorld,
+        stage: DiagnosticStage,
+        diagnostic: &SourceDiagnostic,
+    ) -> Self {
+        let (file, range) = resolve_span(world, diagnostic.span);
+
+        Self {
+            stage,
+            source: None,
+            entity: None,
+            severity: diagnostic.severity,
+            message: diagnostic.message.clone(),
+            hints: diagnostic
+                .hints
+                .iter()
+                .map(|hint| hint.v.clone())
+                .collect(),
+            trace: diagnostic
+                .trace
+                .iter()
+                .map(|point| {
+                    let (file, range) =
+                        resolve_span(world, point.span.into());
+                    DiagnosticTrace {
+                        message: eco_format!("{}", point.v),
+                        file,
+                        range,
+                    }
+                })
+                .collect(),
+            file,
+            range,
+        }
+    }
+
+    /// Create an error without any location.
+    pub fn error(
+        stage: DiagnosticStage,
+        message: impl Into<EcoString>,
+    ) -> Self {
+        Self {
+            stage,
+            source: None,
+            entity: None,
+            severity: Severity::Error,
+            message: message.into(),
+            hints: Vec::new(),
+            trace: Vec::new(),
+            file: None,
+            range: None,
+        }
+    }
+
+    /// Create a warning without any location.
+    pub fn warning(
+        stage: DiagnosticStage,
+        message: impl Into<EcoString>,
+    ) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(stage, message)
+        }
+    }
+
+    /// Attach the source asset this diagnostic originates from.
+    pub fn with_source(
+        mut self,
+        source: AssetId<VelystSource>,
+    ) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Attach the entity this diagnostic originates from.
+    pub fn with_entity(mut self, entity: Entity) -> Self {
+        self.entity = Some(entity);
+        self
+    }
+
+    /// Whether this diagnostic is an error.
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for VelystDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "{}", self.message)?;
+        writeln!(
+            f,
+            "In file: {}",
+            format_location(self.file, self.range)
+        )?;
+        for trace in self.trace.iter() {
+            writeln!(
+                f,
+                "Trace: {} at {}",
+                trace.message,
+                format_location(trace.file, trace.range)
+            )?;
+        }
+        if !self.hints.is_empty() {
+            write!(f, "Hints: {}", self.hints.join("\n"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a file and line/column range as `path:line:column`, with
+/// 1-based line and column numbers.
+pub fn format_location(
+    file: Option<FileId>,
+    range: Option<LineColumnRange>,
+) -> EcoString {
+    let Some(id) = file else {
+        return EcoString::from("<unknown>");
+    };
+
+    let path = match id.root() {
+        VirtualRoot::Package(spec) => {
+            eco_format!("{spec}{}", id.vpath().get_with_slash())
+        }
+        VirtualRoot::Project => {
+            EcoString::from(id.vpath().get_with_slash())
+        }
+    };
+
+    match range {
+        Some(range) => eco_format!(
+            "{path}:{}:{}",
+            range.start.line + 1,
+            range.start.column + 1
+        ),
+        None => path,
+    }
+}
+
+/// Resolve a diagnostic span into a file id and line/column range.
+fn resolve_span(
+    world: &VelystWorld,
+    span: DiagSpan,
+) -> (Option<FileId>, Option<LineColumnRange>) {
+    let Some(id) = span.id() else {
+        return (None, None);
+    };
+
+    let range =
+        world.source(id).ok().zip(world.range(span)).and_then(
+            |(source, range)| {
+                let lines = source.lines();
+                let (start_line, start_col) =
+                    lines.byte_to_line_column(range.start)?;
+                let (end_line, end_col) =
+                    lines.byte_to_line_column(range.end)?;
+                Some(LineColumnRange {
+                    start: LineColumn {
+                        line: start_line,
+                        column: start_col,
+                    },
+                    end: LineColumn {
+                        line: end_line,
+                        column: end_col,
+                    },
+                })
+            },
+        );
+
+    (Some(id), range)
+}
+
+/// A single step in the trace of a [`VelystDiagnostic`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnosticTrace {
+    /// A description of the trace point, e.g. "while calling
+    /// `button`".
+    pub message: EcoString,
+    /// The file the trace point points into, if any.
+    pub file: Option<FileId>,
+    /// The line and column range within [`Self::file`], if
+    /// resolvable.
+    pub range: Option<LineColumnRange>,
+}
+
+/// A zero-based line and column position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineColumn {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A range between two [`LineColumn`] positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineColumnRange {
+    pub start: LineColumn,
+    pub end: LineColumn,
+}
+
+/// The stage of the Velyst pipeline that produced a
+/// [`VelystDiagnostic`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub enum DiagnosticStage {
+    /// Evaluating a [`VelystSource`] into a module.
+    Eval,
+    /// Compiling a [`VelystFunc`][crate::func::VelystFunc] into
+    /// content.
+    Compile,
+    /// Laying out content into a frame.
+    Layout,
+}
+
+/// Errors of the last failed evaluation, compilation or layout of
+/// this entity's [`VelystFunc`][crate::func::VelystFunc], keyed by
+/// the stage that produced them.
+///
+/// Each stage only replaces its own errors. Removed automatically
+/// once every stage succeeds again.
+#[derive(Component, Default, Debug, Clone)]
+pub struct VelystErrors(
+    BTreeMap<DiagnosticStage, Vec<VelystDiagnostic>>,
+);
+
+impl VelystErrors {
+    /// Errors of every stage, in pipeline order.
+    pub fn iter(&self) -> impl Iterator<Item = &VelystDiagnostic> {
+        self.0.values().flatten()
+    }
+
+    /// Errors of a single stage.
+    pub fn stage(
+        &self,
+        stage: DiagnosticStage,
+    ) -> &[VelystDiagnostic] {
+        self.0.get(&stage).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Total number of errors across all stages.
+    pub fn len(&self) -> usize {
+        self.0.values().map(Vec::len).sum()
+    }
+
+    /// Whether no stage has any errors.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Replace the errors of a stage on an entity, inserting or removing
+/// [`VelystErrors`] as needed.
+///
+/// The errors are applied to the entity's current [`VelystErrors`]
+/// when the commands are flushed, so systems updating different
+/// stages never overwrite each other.
+pub(crate) fn set_stage_errors(
+    commands: &mut Commands,
+    entity: Entity,
+    stage: DiagnosticStage,
+    errors: impl IntoIterator<Item = VelystDiagnostic>,
+) {
+    let errors = errors
+        .into_iter()
+        .filter(|diag| diag.is_error())
+        .collect::<Vec<_>>();
+
+    commands.entity(entity).queue_silenced(
+        move |mut entity: EntityWorldMut| {
+            let current = entity
+                .get::<VelystErrors>()
+                .map(|current| current.stage(stage))
+                .unwrap_or_default();
+            if current == errors.as_slice() {
+                return;
+            }
+
+            match entity.get_mut::<VelystErrors>() {
+                Some(mut current) if errors.is_empty() => {
+                    current.0.remove(&stage);
+                    if current.is_empty() {
+                        entity.remove::<VelystErrors>();
+                    }
+                }
+                Some(mut current) => {
+                    current.0.insert(stage, errors);
+                }
+                None => {
+                    entity.insert(VelystErrors(BTreeMap::from([(
+                        stage, errors,
+                    )])));
+                }
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::world::World;
+
+    use super::*;
+
+    fn set_errors(
+        world: &mut World,
+        entity: Entity,
+        stage: DiagnosticStage,
+        messages: &[&str],
+    ) {
+        set_stage_errors(
+            &mut world.commands(),
+            entity,
+            stage,
+            messages.iter().map(|message| {
+                VelystDiagnostic::error(stage, *message)
+            }),
+        );
+        world.flush();
+    }
+
+    #[test]
+    fn stages_keep_their_own_errors() {
+        let mut world = World::new();
+        let entity = world.spawn_empty().id();
+
+        // Both stages are queued before either is applied, like two
+        // systems running in the same frame.
+        let mut commands = world.commands();
+        set_stage_errors(
+            &mut commands,
+            entity,
+            DiagnosticStage::Layout,
+            [VelystDiagnostic::error(DiagnosticStage::Layout, "b")],
+        );
+        set_stage_errors(
+            &mut commands,
+            entity,
+            DiagnosticStage::Eval,
+            [VelystDiagnostic::error(DiagnosticStage::Eval, "a")],
+        );
+        world.flush();
+
+        let errors = world.get::<VelystErrors>(entity).unwrap();
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| e.message.as_str())
+                .collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+
+        set_errors(&mut world, entity, DiagnosticStage::Eval, &[]);
+        let errors = world.get::<VelystErrors>(entity).unwrap();
+        assert!(errors.stage(DiagnosticStage::Eval).is_empty());
+        assert_eq!(errors.len(), 1);
+
+        set_errors(&mut world, entity, DiagnosticStage::Layout, &[]);
+        assert!(world.get::<VelystErrors>(entity).is_none());
+    }
+
+    #[test]
+    fn warnings_are_not_errors() {
+        let mut world = World::new();
+        let entity = world.spawn_empty().id();
+
+        set_stage_errors(
+            &mut world.commands(),
+            entity,
+            DiagnosticStage::Compile,
+            [VelystDiagnostic::warning(
+                DiagnosticStage::Compile,
+                "w",
+            )],
+        );
+        world.flush();
+
+        assert!(world.get::<VelystErrors>(entity).is_none());
+    }
+
+    #[test]
+    fn display_hints_only_when_present() {
+        let mut diag =
+            VelystDiagnostic::error(DiagnosticStage::Eval, "oops");
+        assert!(!diag.to_string().contains("Hints"));
+
+        diag.hints.push("try this".into());
+        assert!(diag.to_string().ends_with("Hints: try this"));
+    }
+}
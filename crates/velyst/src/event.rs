@@ -0,0 +1,293 @@
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use ecow::eco_format;
+use typst::foundations::{FromValue, Value};
+use typst::introspection::{Location, MetadataElem, Tag};
+use typst::layout::{Frame, FrameItem};
+
+use crate::VelystSet;
+use crate::diag::{DiagnosticStage, VelystDiagnostic};
+use crate::renderer::VelystFrame;
+
+/// The label that marks a `metadata` element as a [`VelystEvent`].
+pub const EVENT_LABEL: &str = "velyst-event";
+
+pub struct VelystEventPlugin;
+
+impl Plugin for VelystEventPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<VelystEvent>().add_systems(
+            PostUpdate,
+            collect_events.in_set(VelystSet::PostLayout),
+        );
+    }
+}
+
+pub trait TypstEventAppExt {
+    fn register_typst_event<T: TypstEvent>(&mut self) -> &mut Self;
+}
+
+impl TypstEventAppExt for App {
+    /// Register a [`TypstEvent`] type so that matching
+    /// [`VelystEvent`]s are also sent as [`VelystEvent<T>`].
+    fn register_typst_event<T: TypstEvent>(&mut self) -> &mut Self {
+        self.add_message::<VelystEvent<T>>().add_systems(
+            PostUpdate,
+            convert_events::<T>
+                .after(collect_events)
+                .in_set(VelystSet::PostLayout),
+        )
+    }
+}
+
+/// A value sent from Typst through a `metadata` element labelled
+/// `<velyst-event>`, e.g.
+///
+/// ```typ
+/// #metadata((event: "play-sound", id: "click")) <velyst-event>
+/// ```
+///
+/// Events are sent in document order when they first appear in the
+/// laid out [`VelystFrame`] of an entity. Laying out the same content
+/// again doesn't send them again. [`VelystEvent<Value>`] carries
+/// every event, while [`VelystEvent<T>`] only carries events of a
+/// registered [`TypstEvent`] type.
+#[derive(Message, Debug, Clone)]
+pub struct VelystEvent<T: Send + Sync + 'static = Value> {
+    /// The entity whose frame contained the event.
+    pub entity: Entity,
+    /// The event value.
+    pub event: T,
+}
+
+/// A typed [`VelystEvent`], converted from dictionaries whose `event`
+/// field equals [`Self::NAME`].
+///
+/// Register it with [`TypstEventAppExt::register_typst_event`].
+///
+/// # Example
+///
+/// ```
+/// use velyst::event::TypstEvent;
+/// use velyst::typst::diag::HintedStrResult;
+/// use velyst::typst::foundations::{
+///     CastInfo, Dict, FromValue, Reflect, Value,
+/// };
+///
+/// struct PlaySound {
+///     id: String,
+/// }
+///
+/// impl Reflect for PlaySound {
+///     fn input() -> CastInfo {
+///         Dict::input()
+///     }
+///
+///     fn output() -> CastInfo {
+///         Dict::output()
+///     }
+///
+///     fn castable(value: &Value) -> bool {
+///         Dict::castable(value)
+///     }
+/// }
+///
+/// impl FromValue for PlaySound {
+///     fn from_value(value: Value) -> HintedStrResult<Self> {
+///         let mut dict = Dict::from_value(value)?;
+///         Ok(Self {
+///             id: dict.take("id")?.cast()?,
+///         })
+///     }
+/// }
+///
+/// impl TypstEvent for PlaySound {
+///     const NAME: &str = "play-sound";
+/// }
+/// ```
+pub trait TypstEvent: FromValue + Send + Sync + 'static {
+    const NAME: &str;
+}
+
+/// Send a [`VelystEvent`] for every event in newly laid out frames
+/// that wasn't in the previous frame of the entity.
+fn collect_events(
+    q_frames: Query<(Entity, &VelystFrame), Changed<VelystFrame>>,
+    mut removed: RemovedComponents<VelystFrame>,
+    mut sent: Local<HashMap<Entity, HashSet<Location>>>,
+    mut events: MessageWriter<VelystEvent>,
+) {
+    for entity in removed.read() {
+        sent.remove(&entity);
+    }
+
+    for (entity, frame) in q_frames.iter() {
+        let Some(frame) = &frame.0 else {
+            continue;
+        };
+
+        let mut values = Vec::new();
+        collect_event_values(frame, &mut values);
+
+        let prev = sent.remove(&entity).unwrap_or_default();
+        let mut locations = HashSet::new();
+        for (location, event) in values {
+            let is_new = match location {
+                Some(location) => {
+                    locations.insert(location);
+                    !prev.contains(&location)
+                }
+                None => true,
+            };
+            if is_new {
+                events.write(VelystEvent { entity, event });
+            }
+        }
+        sent.insert(entity, locations);
+    }
+}
+
+/// Collect the locations and values of `metadata` elements labelled
+/// [`EVENT_LABEL`] from the tags of a frame.
+fn collect_event_values(
+    frame: &Frame,
+    values: &mut Vec<(Option<Location>, Value)>,
+) {
+    for (_, item) in frame.items() {
+        match item {
+            FrameItem::Group(group) => {
+                collect_event_values(&group.frame, values)
+            }
+            FrameItem::Tag(Tag::Start(content, _)) => {
+                if content.label().is_some_and(|label| {
+                    label.resolve().as_str() == EVENT_LABEL
+                }) && let Some(metadata) =
+                    content.to_packed::<MetadataElem>()
+                {
+                    values.push((
+                        content.location(),
+                        metadata.value.clone(),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Convert [`VelystEvent`]s named [`TypstEvent::NAME`] into
+/// [`VelystEvent<T>`].
+fn convert_events<T: TypstEvent>(
+    mut untyped: MessageReader<VelystEvent>,
+    mut typed: MessageWriter<VelystEvent<T>>,
+    mut diagnostics: MessageWriter<VelystDiagnostic>,
+) {
+    for VelystEvent { entity, event } in untyped.read() {
+        let Value::Dict(dict) = event else {
+            continue;
+        };
+        let is_named = dict
+            .get("event")
+            .is_ok_and(|name| *name == Value::Str(T::NAME.into()));
+        if !is_named {
+            continue;
+        }
+
+        match T::from_value(event.clone()) {
+            Ok(event) => {
+                typed.write(VelystEvent {
+                    entity: *entity,
+                    event,
+                });
+            }
+            Err(err) => {
+                diagnostics.write(
+                    VelystDiagnostic::warning(
+                        DiagnosticStage::Layout,
+                        eco_format!(
+                            "Invalid {} event: {}",
+                            T::NAME,
+                            err.message()
+                        ),
+                    )
+                    .with_entity(*entity),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::VelystSource;
+    use crate::func::{DynamicVelystFunc, VelystContent};
+    use crate::renderer::WorldScene;
+    use crate::test_utils::{
+        frame_text, test_app, update_until, write_file,
+    };
+
+    #[derive(Resource, Default)]
+    struct Received(usize);
+
+    #[test]
+    fn relayout_sends_no_events() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "main.typ",
+            "#let ping() = [\
+             #metadata((event: \"ping\")) <velyst-event>\
+             Ping\
+             ]",
+        );
+
+        let mut app = test_app(dir.path());
+        app.init_resource::<Received>().add_systems(
+            Last,
+            |mut events: MessageReader<VelystEvent>,
+             mut received: ResMut<Received>| {
+                received.0 += events.read().count();
+            },
+        );
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let entity = app
+            .world_mut()
+            .spawn((
+                DynamicVelystFunc::new(handle, "ping"),
+                WorldScene::default(),
+                Visibility::default(),
+            ))
+            .id();
+
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "Ping"
+        }));
+        assert_eq!(app.world().resource::<Received>().0, 1);
+
+        // Lay the same content out again.
+        let laid_out = app
+            .world()
+            .entity(entity)
+            .get_ref::<VelystFrame>()
+            .unwrap()
+            .last_changed();
+        app.world_mut()
+            .get_mut::<VelystContent>(entity)
+            .unwrap()
+            .set_changed();
+        app.update();
+
+        let relaid_out = app
+            .world()
+            .entity(entity)
+            .get_ref::<VelystFrame>()
+            .unwrap()
+            .last_changed();
+        assert_ne!(laid_out, relaid_out);
+        assert_eq!(app.world().resource::<Received>().0, 1);
+    }
+}
@@ -1,15 +1,42 @@
-use bevy::asset::AsAssetId;
+use std::sync::Arc;
+
+use bevy::asset::{AsAssetId, AssetLoadError, LoadState};
+use bevy::platform::collections::HashSet;
 use bevy::prelude::*;
-use typst::foundations::{Content, IntoValue, NativeElement, Value};
+use ecow::{EcoString, eco_format};
+use typst::foundations::{
+    Content, Dict, Func, IntoValue, NativeElement, Str, Value,
+};
 use typst_element::elem::FuncCall;
 use typst_element::prelude::ScopeExt;
 
 use crate::VelystSet;
-use crate::asset::{VelystModules, VelystSource};
+use crate::asset::{
+    VelystEvalErrors, VelystModuleUpdated, VelystModules,
+    VelystSource,
+};
+use crate::diag::{
+    DiagnosticStage, VelystDiagnostic, set_stage_errors,
+};
 use crate::renderer::VelystFrame;
+use crate::world::VelystWorld;
+use crate::world::inputs::InputsLibrary;
+
+pub mod reflect;
+pub mod signature;
+
+pub use reflect::{
+    ReflectTypstValue, ReflectValueError, VelystFuncReflect,
+    reflect_to_value,
+};
+pub use signature::{TypstFuncParams, TypstFuncSignatures};
 
 pub trait TypstFuncAppExt {
     fn register_typst_func<F: TypstFunc>(&mut self) -> &mut Self;
+
+    fn register_typst_func_reflect<C: Component + Reflect>(
+        &mut self,
+    ) -> &mut Self;
 }
 
 impl TypstFuncAppExt for App {
@@ -17,84 +44,287 @@ impl TypstFuncAppExt for App {
     /// entities are compiled into [`VelystContent`] when they
     /// change.
     fn register_typst_func<F: TypstFunc>(&mut self) -> &mut Self {
+        self.init_resource::<TypstFuncSignatures>().add_systems(
+            PostUpdate,
+            (
+                signature::record_signatures::<F, VelystFunc<F>>,
+                check_source_ready::<VelystFunc<F>>,
+                compile_velyst_func::<VelystFunc<F>>,
+            )
+                .chain()
+                .in_set(VelystSet::Compile),
+        )
+    }
+
+    /// Register a reflected component so that
+    /// [`VelystFuncReflect<C>`] entities are compiled into
+    /// [`VelystContent`] when the function or `C` changes.
+    fn register_typst_func_reflect<C: Component + Reflect>(
+        &mut self,
+    ) -> &mut Self {
         self.add_systems(
             PostUpdate,
-            (check_source_ready::<F>, compile_velyst_func::<F>)
+            (
+                reflect::update_reflect_value::<C>,
+                check_source_ready::<VelystFuncReflect<C>>,
+                compile_velyst_func::<VelystFuncReflect<C>>,
+            )
                 .chain()
                 .in_set(VelystSet::Compile),
         )
     }
 }
 
+pub struct VelystFuncPlugin;
+
+impl Plugin for VelystFuncPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (
+                check_source_ready::<DynamicVelystFunc>,
+                compile_velyst_func::<DynamicVelystFunc>,
+            )
+                .chain()
+                .in_set(VelystSet::Compile),
+        );
+    }
+}
+
+/// A component that calls a Typst function from the module of its
+/// source asset.
+trait FuncComponent: Component + AsAssetId<Asset = VelystSource> {
+    /// The path of the function in the module's scope.
+    fn func_name(&self) -> &str;
+
+    /// Collect the positional and named arguments of the call, or
+    /// the error that prevents calling it.
+    fn collect_args<'a>(
+        &'a self,
+        positional_args: &mut Vec<Value>,
+        named_args: &mut Vec<(&'a str, Value)>,
+    ) -> Result<(), &'a VelystDiagnostic>;
+}
+
 /// Insert or remove [`VelystSourceReady`] based on whether the module
-/// for the entity's handle is loaded.
-fn check_source_ready<F: TypstFunc>(
+/// for the entity's handle is evaluated and all of its imports are
+/// done loading, and attach evaluation errors of that module to the
+/// entity.
+///
+/// Imports that failed to load do not block the entity, since the
+/// module evaluated without them, e.g. when they are imported in a
+/// branch that never runs. A warning is sent instead.
+///
+/// This is why readiness is not derived from
+/// [`AssetEvent::LoadedWithDependencies`]: Bevy never sends it for a
+/// source with a failed import, and its recursive load state fails
+/// as soon as one import does, while others may still be loading.
+fn check_source_ready<C: FuncComponent>(
     mut commands: Commands,
-    q_funcs: Query<(Entity, &VelystFunc<F>, Has<VelystSourceReady>)>,
+    q_funcs: Query<(Entity, Ref<C>, Has<VelystSourceReady>)>,
     modules: Res<VelystModules>,
+    eval_errors: Res<VelystEvalErrors>,
+    sources: Res<Assets<VelystSource>>,
+    asset_server: Res<AssetServer>,
+    mut diagnostics: MessageWriter<VelystDiagnostic>,
 ) {
     for (entity, func, is_ready) in q_funcs.iter() {
-        let module_ready = modules.contains_key(&func.handle.id());
+        let id = func.as_asset_id();
+        let failed_imports =
+            settled_imports(id, &sources, &asset_server);
+        let module_ready =
+            modules.contains_key(&id) && failed_imports.is_some();
 
         if module_ready && !is_ready {
+            for err in failed_imports.into_iter().flatten() {
+                diagnostics.write(
+                    VelystDiagnostic::warning(
+                        DiagnosticStage::Eval,
+                        eco_format!(
+                            "Unable to load an import: {err}"
+                        ),
+                    )
+                    .with_source(id)
+                    .with_entity(entity),
+                );
+            }
             commands.entity(entity).insert(VelystSourceReady);
         } else if !module_ready && is_ready {
             commands.entity(entity).remove::<VelystSourceReady>();
         }
+
+        if eval_errors.is_changed() || func.is_changed() {
+            set_stage_errors(
+                &mut commands,
+                entity,
+                DiagnosticStage::Eval,
+                eval_errors
+                    .get(&id)
+                    .into_iter()
+                    .flatten()
+                    .map(|diag| diag.clone().with_entity(entity)),
+            );
+        }
     }
 }
 
-/// Compile a [`VelystFunc<F>`] into a [`VelystContent`].
-fn compile_velyst_func<F: TypstFunc>(
+/// Errors of the Typst modules imported by a source, directly or
+/// through other imports, once all of them are done loading.
+///
+/// Returns `None` while any of them is still loading.
+fn settled_imports(
+    id: AssetId<VelystSource>,
+    sources: &Assets<VelystSource>,
+    asset_server: &AssetServer,
+) -> Option<Vec<Arc<AssetLoadError>>> {
+    let mut failed = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![id];
+
+    while let Some(id) = stack.pop() {
+        if !visited.insert(id) {
+            continue;
+        }
+        let Some(source) = sources.get(id) else {
+            continue;
+        };
+
+        for import in source.imports() {
+            match asset_server.load_state(import) {
+                LoadState::Loaded => stack.push(import.id()),
+                LoadState::Failed(err) => failed.push(err),
+                LoadState::NotLoaded | LoadState::Loading => {
+                    return None;
+                }
+            }
+        }
+    }
+
+    Some(failed)
+}
+
+/// Compile a [`VelystFunc<F>`] or [`DynamicVelystFunc`] into a
+/// [`VelystContent`].
+fn compile_velyst_func<C: FuncComponent>(
+    mut commands: Commands,
     mut q_funcs: Query<(
-        Ref<VelystFunc<F>>,
+        Entity,
+        Ref<C>,
         &mut VelystContent,
         Ref<Visibility>,
         Ref<VelystSourceReady>,
+        Option<Ref<InputsLibrary>>,
     )>,
+    world: VelystWorld,
     modules: Res<VelystModules>,
-    mut asset_events: MessageReader<AssetEvent<VelystSource>>,
+    sources: Res<Assets<VelystSource>>,
+    mut module_updates: MessageReader<VelystModuleUpdated>,
+    mut diagnostics: MessageWriter<VelystDiagnostic>,
 ) {
     let changed_assets: smallvec::SmallVec<
         [AssetId<VelystSource>; 4],
-    > = asset_events
-        .read()
-        .filter_map(|e| match e {
-            AssetEvent::Added { id }
-            | AssetEvent::Modified { id } => Some(*id),
-            _ => None,
-        })
-        .collect();
-
-    for (func, mut content, viz, ready) in q_funcs.iter_mut() {
+    > = module_updates.read().map(|e| e.id).collect();
+
+    for (entity, func, mut content, viz, ready, library) in
+        q_funcs.iter_mut()
+    {
+        let id = func.as_asset_id();
         let needs_recompile = func.is_changed()
             || viz.is_changed()
             || ready.is_added()
-            || changed_assets.contains(&func.handle.id());
+            || changed_assets.contains(&id)
+            || library.as_ref().is_some_and(|l| l.is_changed());
 
         if !needs_recompile || *viz == Visibility::Hidden {
             continue;
         }
 
-        let Some(module) = modules.get(&func.handle.id()) else {
-            continue;
+        let mut stage_errors = Vec::new();
+
+        // Entities with their own inputs use a module evaluated with
+        // their own library.
+        let entity_module = library
+            .as_ref()
+            .and_then(|library| library.0.as_ref())
+            .zip(sources.get(id))
+            .map(|(library, source)| {
+                let result = world.eval_source_with(source, library);
+                for diag in result.diagnostics.iter() {
+                    let diag = VelystDiagnostic::from_source(
+                        &world,
+                        DiagnosticStage::Compile,
+                        diag,
+                    )
+                    .with_source(id)
+                    .with_entity(entity);
+
+                    if diag.is_error() {
+                        stage_errors.push(diag.clone());
+                    }
+                    diagnostics.write(diag);
+                }
+                result.output
+            });
+
+        let module = match &entity_module {
+            Some(module) => module.as_ref(),
+            None => modules.get(&id),
         };
 
-        match module.scope().get_func(F::NAME) {
-            Ok(typst_func) => {
-                let mut positional_args = Vec::new();
-                let mut named_args = Vec::new();
-                func.data.apply_positional_args(&mut positional_args);
-                func.data.apply_named_args(&mut named_args);
-                content.0 = typst_func
-                    .call_with_named(&positional_args, &named_args)
-                    .pack();
+        if let Some(module) = module {
+            match module.scope().get_path::<Func>(func.func_name()) {
+                Ok(typst_func) => {
+                    let mut positional_args = Vec::new();
+                    let mut named_args = Vec::new();
+                    match func.collect_args(
+                        &mut positional_args,
+                        &mut named_args,
+                    ) {
+                        Ok(()) => {
+                            // Span the content with the function, so
+                            // that layout knows where it came from.
+                            let span = typst_func.span();
+                            content.0 = typst_func
+                                .call_with_named(
+                                    &positional_args,
+                                    &named_args,
+                                )
+                                .pack()
+                                .spanned(span);
+                        }
+                        Err(diag) => {
+                            let diag = diag
+                                .clone()
+                                .with_source(id)
+                                .with_entity(entity);
+                            stage_errors.push(diag.clone());
+                            diagnostics.write(diag);
+                        }
+                    }
+                }
+                Err(err) => {
+                    let diag = VelystDiagnostic::error(
+                        DiagnosticStage::Compile,
+                        eco_format!(
+                            "Unable to get typst function {}: {err}",
+                            func.func_name()
+                        ),
+                    )
+                    .with_source(id)
+                    .with_entity(entity);
+
+                    stage_errors.push(diag.clone());
+                    diagnostics.write(diag);
+                }
             }
-            Err(err) => error!(
-                "Unable to get typst function {}: {err}",
-                F::NAME
-            ),
         }
+
+        set_stage_errors(
+            &mut commands,
+            entity,
+            DiagnosticStage::Compile,
+            stage_errors,
+        );
     }
 }
 
@@ -121,15 +351,129 @@ impl<F: TypstFunc> AsAssetId for VelystFunc<F> {
     }
 }
 
+impl<F: TypstFunc> FuncComponent for VelystFunc<F> {
+    fn func_name(&self) -> &str {
+        F::NAME
+    }
+
+    fn collect_args<'a>(
+        &'a self,
+        positional_args: &mut Vec<Value>,
+        named_args: &mut Vec<(&'a str, Value)>,
+    ) -> Result<(), &'a VelystDiagnostic> {
+        self.data.apply_positional_args(positional_args);
+
+        let mut static_args = Vec::new();
+        self.data.apply_named_args(&mut static_args);
+        named_args.extend(static_args);
+        Ok(())
+    }
+}
+
 impl<F: TypstFunc> VelystFunc<F> {
     pub fn new(handle: Handle<VelystSource>, data: F) -> Self {
         Self { handle, data }
     }
 }
 
+/// A Typst function component whose name and arguments are only
+/// known at runtime, e.g. when UI screens are driven from data files,
+/// mod scripts or an editor.
+///
+/// Compiled into [`VelystContent`] like [`VelystFunc`], without
+/// registering a Rust type per function.
+///
+/// # Example
+///
+/// ```
+/// use bevy::prelude::*;
+/// use velyst::prelude::*;
+///
+/// fn spawn(mut commands: Commands, asset_server: Res<AssetServer>) {
+///     commands.spawn((
+///         DynamicVelystFunc::new(
+///             asset_server.load("menu.typ"),
+///             "button",
+///         )
+///         .with_positional("Play")
+///         .with_named("size", 24.0),
+///         UiScene,
+///     ));
+/// }
+/// ```
+#[derive(Component, Clone)]
+#[require(VelystContent)]
+pub struct DynamicVelystFunc {
+    pub handle: Handle<VelystSource>,
+    /// The name of the function in the module's scope, or a dotted
+    /// path such as [`TypstFunc::NAME`].
+    pub name: EcoString,
+    pub positional: Vec<Value>,
+    pub named: Dict,
+}
+
+impl DynamicVelystFunc {
+    pub fn new(
+        handle: Handle<VelystSource>,
+        name: impl Into<EcoString>,
+    ) -> Self {
+        Self {
+            handle,
+            name: name.into(),
+            positional: Vec::new(),
+            named: Dict::new(),
+        }
+    }
+
+    /// Append a positional argument.
+    pub fn with_positional(mut self, value: impl IntoValue) -> Self {
+        self.positional.push(value.into_value());
+        self
+    }
+
+    /// Set a named argument.
+    pub fn with_named(
+        mut self,
+        name: impl Into<Str>,
+        value: impl IntoValue,
+    ) -> Self {
+        self.named.insert(name.into(), value.into_value());
+        self
+    }
+}
+
+impl AsAssetId for DynamicVelystFunc {
+    type Asset = VelystSource;
+
+    fn as_asset_id(&self) -> AssetId<Self::Asset> {
+        self.handle.id()
+    }
+}
+
+impl FuncComponent for DynamicVelystFunc {
+    fn func_name(&self) -> &str {
+        &self.name
+    }
+
+    fn collect_args<'a>(
+        &'a self,
+        positional_args: &mut Vec<Value>,
+        named_args: &mut Vec<(&'a str, Value)>,
+    ) -> Result<(), &'a VelystDiagnostic> {
+        positional_args.extend(self.positional.iter().cloned());
+        named_args.extend(
+            self.named
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.clone())),
+        );
+        Ok(())
+    }
+}
+
 /// Marker component that is inserted when the
 /// [module][typst::foundations::Module] needed for this entity's
-/// [`VelystFunc`] handle is ready.
+/// [`VelystFunc`] handle is ready, along with every module it
+/// imports.
 ///
 /// Will be removed when the [module][typst::foundations::Module]
 /// needed becomes unavailable again.
@@ -147,9 +491,47 @@ pub trait TypstValue:
 
 impl<T: IntoValue + Clone + Send + Sync + 'static> TypstValue for T {}
 
+pub use velyst_macros::TypstFunc;
+
+/// A Typst function with typed arguments, used by [`VelystFunc`] and
+/// [`TypstQuery`][crate::query::TypstQuery].
+///
+/// Usually derived, see [`derive@TypstFunc`] for every attribute.
+///
+/// # Example
+///
+/// ```
+/// use bevy::prelude::*;
+/// use velyst::prelude::*;
+///
+/// /// A button function from Typst.
+/// #[derive(TypstFunc, Component, Reflect)]
+/// #[typst(name = "widgets.button")]
+/// #[reflect(Component)]
+/// struct ButtonFunc<T: TypstValue + Reflect> {
+///     /// Button label, positional arguments are passed in order.
+///     label: String,
+///     custom_data: T,
+///     /// Omitted when `None`, so that the Typst default applies.
+///     #[typst(named)]
+///     icon: Option<u32>,
+///     #[typst(named, rename = "font-size", default = 16.0)]
+///     font_size: Option<f64>,
+///     /// Only used on the Rust side.
+///     #[typst(skip)]
+///     clicks: u32,
+/// }
+/// ```
 pub trait TypstFunc: Send + Sync + 'static {
+    /// The name of the function in the module's scope, or a dotted
+    /// path into nested modules, e.g. `widgets.button`.
     const NAME: &str;
 
+    /// The arguments passed to Typst, checked against the Typst
+    /// definition whenever a module is evaluated. `None` skips the
+    /// check.
+    const PARAMS: Option<TypstFuncParams> = None;
+
     fn apply_positional_args(&self, args: &mut Vec<Value>);
 
     fn apply_named_args(&self, args: &mut Vec<(&'static str, Value)>);
@@ -158,6 +540,9 @@ pub trait TypstFunc: Send + Sync + 'static {
 /// Helper macro for creating Typst function struct with
 /// [`TypstFunc`] trait implementation.
 ///
+/// Prefer [`derive@TypstFunc`], which also supports default values,
+/// renamed arguments and skipped fields.
+///
 /// # Example
 ///
 /// ```
@@ -253,6 +638,11 @@ macro_rules! typst_func {
         $(< $( $generic ),+ >)?
         {
             const NAME: &'static str = $str_name;
+            const PARAMS: Option<$crate::func::TypstFuncParams> =
+                Some($crate::func::TypstFuncParams {
+                    positional: &[$($(stringify!($positional_arg)),*)?],
+                    named: &[$($(stringify!($named_arg)),*)?],
+                });
 
             fn apply_positional_args(&self, args: &mut Vec<$crate::typst::foundations::Value>) {
                 args.clear();
@@ -279,3 +669,85 @@ macro_rules! typst_func {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_app, update_until, write_file};
+
+    crate::typst_func!("card", struct CardFunc {});
+
+    #[derive(TypstFunc)]
+    #[typst(name = "label", crate = "crate")]
+    struct LabelFunc {
+        text: &'static str,
+        #[typst(rename = "font-size", default = 12.0)]
+        size: Option<f64>,
+        #[typst(skip)]
+        _id: u32,
+    }
+
+    #[test]
+    fn derive_with_crate_path() {
+        let func = LabelFunc {
+            text: "Hi",
+            size: None,
+            _id: 0,
+        };
+        let params = LabelFunc::PARAMS.unwrap();
+        assert_eq!(params.positional, ["text"]);
+        assert_eq!(params.named, ["font-size"]);
+
+        let mut named = Vec::new();
+        func.apply_named_args(&mut named);
+        assert_eq!(named, [("font-size", Value::Float(12.0))]);
+    }
+
+    #[test]
+    fn failed_import_does_not_block_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "main.typ",
+            "#let card() = [Card]\n\
+             #let unused() = if false { import \"missing.typ\" }",
+        );
+
+        let mut app = test_app(dir.path());
+        app.register_typst_func::<CardFunc>();
+
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let entity = app
+            .world_mut()
+            .spawn((
+                VelystFunc::new(handle, CardFunc {}),
+                Visibility::default(),
+            ))
+            .id();
+
+        let mut warnings = Vec::new();
+        update_until(&mut app, |app| {
+            warnings.extend(
+                app.world()
+                    .resource::<Messages<VelystDiagnostic>>()
+                    .iter_current_update_messages()
+                    .filter(|diag| diag.entity == Some(entity))
+                    .cloned(),
+            );
+            app.world().get::<VelystSourceReady>(entity).is_some()
+        });
+
+        assert!(
+            app.world().get::<VelystSourceReady>(entity).is_some(),
+            "a failed import blocked the source"
+        );
+        assert!(
+            warnings.iter().any(|diag| !diag.is_error()
+                && diag.message.contains("missing.typ")),
+            "{warnings:?}"
+        );
+    }
+}
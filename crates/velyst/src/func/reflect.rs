@@ -0,0 +1,497 @@
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+use bevy::asset::AsAssetId;
+use bevy::prelude::*;
+use bevy::reflect::{
+    Enum, FromType, ReflectRef, TypeRegistry, VariantField,
+    VariantInfo, VariantType,
+};
+use ecow::{EcoString, eco_format};
+use typst::foundations::{Array, Dict, IntoValue, Str, Value};
+
+use super::{FuncComponent, VelystContent};
+use crate::asset::VelystSource;
+use crate::diag::{DiagnosticStage, VelystDiagnostic};
+
+/// Type data that converts a reflected type into a Typst [`Value`]
+/// with its [`IntoValue`] implementation, instead of walking its
+/// fields.
+///
+/// Register it with `#[reflect(TypstValue)]` or
+/// [`App::register_type_data`].
+#[derive(Clone)]
+pub struct ReflectTypstValue {
+    into_value: fn(&dyn PartialReflect) -> Option<Value>,
+}
+
+impl ReflectTypstValue {
+    /// Create from a conversion of its own, for types that do not
+    /// implement [`IntoValue`], such as foreign types.
+    pub fn new(
+        into_value: fn(&dyn PartialReflect) -> Option<Value>,
+    ) -> Self {
+        Self { into_value }
+    }
+
+    /// Convert a value of the registered type, returning `None` for
+    /// any other type.
+    pub fn into_value(
+        &self,
+        value: &dyn PartialReflect,
+    ) -> Option<Value> {
+        (self.into_value)(value)
+    }
+}
+
+impl<T: Reflect + IntoValue + Clone> FromType<T>
+    for ReflectTypstValue
+{
+    fn from_type() -> Self {
+        Self {
+            into_value: |value| {
+                value
+                    .try_downcast_ref::<T>()
+                    .map(|value| value.clone().into_value())
+            },
+        }
+    }
+}
+
+/// Convert a reflected value into a Typst [`Value`].
+///
+/// Types with [`ReflectTypstValue`] use their [`IntoValue`]
+/// implementation. Otherwise, structs and maps become dictionaries,
+/// lists, sets, arrays and tuples become arrays, and newtype structs
+/// become their inner value. Unit enum variants become strings and
+/// other variants become single-entry dictionaries, e.g.
+/// `(heal: 20)`, matching `typst_element::serde`.
+///
+/// Primitives, strings and [`Option`]s are converted directly.
+pub fn reflect_to_value(
+    value: &dyn PartialReflect,
+    registry: &TypeRegistry,
+) -> Result<Value, ReflectValueError> {
+    if let Some(value) = value
+        .get_represented_type_info()
+        .and_then(|info| {
+            registry
+                .get_type_data::<ReflectTypstValue>(info.type_id())
+        })
+        .and_then(|data| data.into_value(value))
+    {
+        return Ok(value);
+    }
+
+    let fields =
+        |fields: &mut dyn Iterator<Item = &dyn PartialReflect>| {
+            fields
+                .map(|field| reflect_to_value(field, registry))
+                .collect::<Result<Array, _>>()
+                .map(Value::Array)
+        };
+
+    match value.reflect_ref() {
+        ReflectRef::Struct(value) => {
+            let mut dict = Dict::new();
+            for (index, field) in value.iter_fields().enumerate() {
+                let name = value.name_at(index).unwrap_or_default();
+                dict.insert(
+                    name.into(),
+                    reflect_to_value(field, registry)?,
+                );
+            }
+            Ok(Value::Dict(dict))
+        }
+        ReflectRef::TupleStruct(value) if value.field_len() == 1 => {
+            reflect_to_value(value.field(0).unwrap(), registry)
+        }
+        ReflectRef::TupleStruct(value) => {
+            fields(&mut value.iter_fields())
+        }
+        ReflectRef::Tuple(value) => fields(&mut value.iter_fields()),
+        ReflectRef::List(value) => fields(&mut value.iter()),
+        ReflectRef::Array(value) => fields(&mut value.iter()),
+        ReflectRef::Set(value) => fields(&mut value.iter()),
+        ReflectRef::Map(value) => {
+            let mut dict = Dict::new();
+            for (key, field) in value.iter() {
+                dict.insert(
+                    map_key(key)?,
+                    reflect_to_value(field, registry)?,
+                );
+            }
+            Ok(Value::Dict(dict))
+        }
+        ReflectRef::Enum(value) => {
+            // `Option` maps to `none` or its inner value.
+            if is_option(value) {
+                return match value.field_at(0) {
+                    Some(field) => reflect_to_value(field, registry),
+                    None => Ok(Value::None),
+                };
+            }
+
+            let variant = Str::from(value.variant_name());
+            let inner = match value.variant_type() {
+                VariantType::Unit => return Ok(Value::Str(variant)),
+                VariantType::Tuple if value.field_len() == 1 => {
+                    reflect_to_value(
+                        value.field_at(0).unwrap(),
+                        registry,
+                    )?
+                }
+                VariantType::Tuple => {
+                    fields(&mut value.iter_fields().map(|field| {
+                        match field {
+                            VariantField::Tuple(field)
+                            | VariantField::Struct(_, field) => field,
+                        }
+                    }))?
+                }
+                VariantType::Struct => {
+                    let mut dict = Dict::new();
+                    for field in value.iter_fields() {
+                        if let VariantField::Struct(name, field) =
+                            field
+                        {
+                            dict.insert(
+                                name.into(),
+                                reflect_to_value(field, registry)?,
+                            );
+                        }
+                    }
+                    Value::Dict(dict)
+                }
+            };
+
+            let mut dict = Dict::new();
+            dict.insert(variant, inner);
+            Ok(Value::Dict(dict))
+        }
+        _ => opaque_to_value(value).ok_or_else(|| {
+            ReflectValueError::Unsupported(
+                value.reflect_type_path().into(),
+            )
+        }),
+    }
+}
+
+/// Whether an enum is an [`Option`], judged by its variants since
+/// its inner type is unknown.
+fn is_option(value: &dyn Enum) -> bool {
+    match value.get_represented_enum_info() {
+        Some(info) => {
+            info.variant_len() == 2
+                && matches!(
+                    info.variant("None"),
+                    Some(VariantInfo::Unit(_))
+                )
+                && matches!(
+                    info.variant("Some"),
+                    Some(VariantInfo::Tuple(variant))
+                        if variant.field_len() == 1
+                )
+        }
+        // Dynamic enums only know their current variant.
+        None => match value.variant_type() {
+            VariantType::Unit => value.variant_name() == "None",
+            VariantType::Tuple => {
+                value.variant_name() == "Some"
+                    && value.field_len() == 1
+            }
+            VariantType::Struct => false,
+        },
+    }
+}
+
+/// Convert a primitive or a string.
+fn opaque_to_value(value: &dyn PartialReflect) -> Option<Value> {
+    macro_rules! downcast {
+        ($($ty:ty),+ $(,)?) => {
+            $(
+                if let Some(value) = value.try_downcast_ref::<$ty>() {
+                    return Some(value.clone().into_value());
+                }
+            )+
+        };
+    }
+
+    downcast!(
+        bool,
+        i8,
+        i16,
+        i32,
+        i64,
+        isize,
+        u8,
+        u16,
+        u32,
+        u64,
+        usize,
+        f32,
+        f64,
+        char,
+        String,
+        &'static str,
+        Cow<'static, str>,
+    );
+    None
+}
+
+/// Convert a map key into a dictionary key.
+fn map_key(
+    key: &dyn PartialReflect,
+) -> Result<Str, ReflectValueError> {
+    match opaque_to_value(key) {
+        Some(Value::Str(key)) => Ok(key),
+        Some(Value::Int(key)) => Ok(eco_format!("{key}").into()),
+        Some(Value::Bool(key)) => Ok(eco_format!("{key}").into()),
+        _ => Err(ReflectValueError::UnsupportedKey(
+            key.reflect_type_path().into(),
+        )),
+    }
+}
+
+#[derive(Debug)]
+pub enum ReflectValueError {
+    /// A type without [`ReflectTypstValue`] that cannot be walked.
+    Unsupported(EcoString),
+    /// A map key that is not a string, integer or boolean.
+    UnsupportedKey(EcoString),
+}
+
+impl std::fmt::Display for ReflectValueError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        match self {
+            ReflectValueError::Unsupported(ty) => {
+                write!(
+                    f,
+                    "`{ty}` cannot be converted into a Typst value!"
+                )
+            }
+            ReflectValueError::UnsupportedKey(ty) => {
+                write!(
+                    f,
+                    "`{ty}` cannot be used as a dictionary key!"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReflectValueError {}
+
+/// Calls a Typst function with the reflected component `C` of the
+/// same entity as its only positional argument, recompiling whenever
+/// `C` changes.
+///
+/// Register it with
+/// [`TypstFuncAppExt::register_typst_func_reflect`][super::TypstFuncAppExt::register_typst_func_reflect].
+/// `C` is converted with [`reflect_to_value`].
+///
+/// # Example
+///
+/// ```
+/// use bevy::prelude::*;
+/// use velyst::prelude::*;
+///
+/// /// Passed to `#let hud(player) = [#player.health]`.
+/// #[derive(Component, Reflect)]
+/// struct Player {
+///     health: u32,
+///     name: String,
+/// }
+///
+/// fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+///     commands.spawn((
+///         Player {
+///             health: 100,
+///             name: "Ferris".into(),
+///         },
+///         VelystFuncReflect::<Player>::new(
+///             asset_server.load("hud.typ"),
+///             "hud",
+///         ),
+///         UiScene,
+///     ));
+/// }
+///
+/// App::new().register_typst_func_reflect::<Player>();
+/// ```
+#[derive(Component)]
+#[require(VelystContent)]
+pub struct VelystFuncReflect<C: Component + Reflect> {
+    pub handle: Handle<VelystSource>,
+    /// The name of the function in the module's scope, or a dotted
+    /// path such as [`TypstFunc::NAME`][super::TypstFunc::NAME].
+    pub name: EcoString,
+    /// The last conversion of `C`, or the error reported when
+    /// compiling.
+    value: Option<Result<Value, VelystDiagnostic>>,
+    marker: PhantomData<fn() -> C>,
+}
+
+impl<C: Component + Reflect> VelystFuncReflect<C> {
+    pub fn new(
+        handle: Handle<VelystSource>,
+        name: impl Into<EcoString>,
+    ) -> Self {
+        Self {
+            handle,
+            name: name.into(),
+            value: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<C: Component + Reflect> AsAssetId for VelystFuncReflect<C> {
+    type Asset = VelystSource;
+
+    fn as_asset_id(&self) -> AssetId<Self::Asset> {
+        self.handle.id()
+    }
+}
+
+impl<C: Component + Reflect> FuncComponent for VelystFuncReflect<C> {
+    fn func_name(&self) -> &str {
+        &self.name
+    }
+
+    fn collect_args<'a>(
+        &'a self,
+        positional_args: &mut Vec<Value>,
+        _named_args: &mut Vec<(&'a str, Value)>,
+    ) -> Result<(), &'a VelystDiagnostic> {
+        match &self.value {
+            Some(Ok(value)) => positional_args.push(value.clone()),
+            Some(Err(diag)) => return Err(diag),
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Convert `C` into the argument of its [`VelystFuncReflect<C>`]
+/// when either of them changes.
+///
+/// Conversion errors are reported when compiling, so that they end up
+/// in the entity's [`VelystErrors`][crate::diag::VelystErrors].
+pub(super) fn update_reflect_value<C: Component + Reflect>(
+    mut q_funcs: Query<(&mut VelystFuncReflect<C>, Ref<C>)>,
+    registry: Res<AppTypeRegistry>,
+) {
+    let registry = registry.read();
+
+    for (mut func, component) in q_funcs.iter_mut() {
+        if !component.is_changed() && !func.is_changed() {
+            continue;
+        }
+
+        let value = reflect_to_value(
+            component.as_partial_reflect(),
+            &registry,
+        )
+        .map_err(|err| {
+            let mut diag = VelystDiagnostic::error(
+                DiagnosticStage::Compile,
+                eco_format!(
+                    "Unable to pass `{}` to typst function {}: {err}",
+                    component.reflect_type_path(),
+                    func.name
+                ),
+            );
+            diag.hints.push(
+                "register `ReflectTypstValue` for types that \
+                 implement `IntoValue`"
+                    .into(),
+            );
+            diag
+        });
+        func.value = Some(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::reflect::{DynamicEnum, DynamicTuple, DynamicVariant};
+    use typst::foundations::dict;
+
+    use super::*;
+    use crate::diag::VelystErrors;
+    use crate::func::TypstFuncAppExt;
+    use crate::test_utils::{test_app, update_until, write_file};
+
+    /// Has a `Some` variant, but is not an [`Option`].
+    #[derive(Reflect)]
+    enum Slot {
+        Empty,
+        Some(u32),
+    }
+
+    #[derive(Component, Reflect)]
+    struct Cooldown {
+        left: Duration,
+    }
+
+    #[test]
+    fn options_are_detected_by_variants() {
+        let registry = TypeRegistry::default();
+        let convert = |value: &dyn PartialReflect| {
+            reflect_to_value(value, &registry).unwrap()
+        };
+
+        assert_eq!(convert(&Some(3_u32)), Value::Int(3));
+        assert_eq!(convert(&None::<u32>), Value::None);
+        assert_eq!(
+            convert(&Slot::Some(3)),
+            Value::Dict(dict! { "Some" => 3 })
+        );
+        assert_eq!(convert(&Slot::Empty), Value::Str("Empty".into()));
+
+        // Without a represented type, only the variant is known.
+        let none = DynamicEnum::new("None", DynamicVariant::Unit);
+        assert_eq!(convert(&none), Value::None);
+        let mut field = DynamicTuple::default();
+        field.insert(3_u32);
+        let some = DynamicEnum::new("Some", field);
+        assert_eq!(convert(&some), Value::Int(3));
+    }
+
+    #[test]
+    fn conversion_errors_are_velyst_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "main.typ", "#let hud(cooldown) = []");
+
+        let mut app = test_app(dir.path());
+        app.register_typst_func_reflect::<Cooldown>();
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let entity = app
+            .world_mut()
+            .spawn((
+                Cooldown {
+                    left: Duration::from_secs(1),
+                },
+                VelystFuncReflect::<Cooldown>::new(handle, "hud"),
+                Visibility::default(),
+            ))
+            .id();
+
+        assert!(update_until(&mut app, |app| {
+            app.world().get::<VelystErrors>(entity).is_some()
+        }));
+        let errors = app.world().get::<VelystErrors>(entity).unwrap();
+        let errors = errors.stage(DiagnosticStage::Compile);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("Duration"));
+    }
+}
@@ -0,0 +1,403 @@
+use bevy::asset::{AsAssetId, AssetPath};
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use ecow::{EcoString, eco_format};
+use typst::diag::SourceDiagnostic;
+use typst::foundations::{Binding, Module, Value};
+use typst::syntax::Span;
+use typst::syntax::ast::{self, AstNode};
+use typst_element::prelude::ScopeExt;
+
+use super::TypstFunc;
+use crate::asset::{VelystModules, VelystSource};
+use crate::diag::{DiagnosticStage, VelystDiagnostic};
+use crate::world::VelystWorld;
+
+/// The arguments a [`TypstFunc`] passes to Typst.
+#[derive(Debug, Clone, Copy)]
+pub struct TypstFuncParams {
+    /// Names of the positional arguments on the Rust side, in order.
+    pub positional: &'static [&'static str],
+    /// Typst names of the named arguments.
+    pub named: &'static [&'static str],
+}
+
+/// Signatures of the [`TypstFunc`]s registered with
+/// [`TypstFuncAppExt::register_typst_func`][super::TypstFuncAppExt::register_typst_func],
+/// keyed by the path of each source they are called from.
+///
+/// Every evaluated module is checked against the signatures of its
+/// own source only.
+#[derive(Resource, Default)]
+pub struct TypstFuncSignatures(
+    HashMap<AssetPath<'static>, Vec<FuncSignature>>,
+);
+
+impl TypstFuncSignatures {
+    /// Record the signature of a [`TypstFunc`] called from the given
+    /// source, if it has one. Returns whether it was not recorded
+    /// yet.
+    pub fn insert<F: TypstFunc>(
+        &mut self,
+        source: AssetPath<'static>,
+    ) -> bool {
+        let Some(sig) = FuncSignature::of::<F>() else {
+            return false;
+        };
+
+        let sigs = self.0.entry(source).or_default();
+        if sigs.iter().any(|s| s.type_name == sig.type_name) {
+            return false;
+        }
+        sigs.push(sig);
+        true
+    }
+
+    /// Warnings for every signature recorded for a source that does
+    /// not match the function of the same name in its module.
+    pub fn check(
+        &self,
+        source: &AssetPath,
+        module: &Module,
+        world: &dyn typst::World,
+    ) -> Vec<SourceDiagnostic> {
+        let mut warnings = Vec::new();
+        for sig in self.0.get(source).into_iter().flatten() {
+            sig.check(module, world, &mut warnings);
+        }
+        warnings
+    }
+}
+
+/// The expected signature of a [`TypstFunc`].
+struct FuncSignature {
+    type_name: &'static str,
+    name: &'static str,
+    params: TypstFuncParams,
+}
+
+impl FuncSignature {
+    fn of<F: TypstFunc>() -> Option<Self> {
+        Some(Self {
+            type_name: std::any::type_name::<F>(),
+            name: F::NAME,
+            params: F::PARAMS?,
+        })
+    }
+
+    fn check(
+        &self,
+        module: &Module,
+        world: &dyn typst::World,
+        warnings: &mut Vec<SourceDiagnostic>,
+    ) {
+        let Some(ClosureSignature {
+            span: func_span,
+            positional,
+            named,
+            has_sink,
+        }) =
+            module.scope().get_binding(self.name).ok().and_then(
+                |binding| ClosureSignature::of(binding, world),
+            )
+        else {
+            return;
+        };
+
+        let type_name = self.type_name;
+        let func_name = self.name;
+
+        // Positional parameters in Typst are always required.
+        for (name, span) in
+            positional.iter().skip(self.params.positional.len())
+        {
+            let name = name.as_deref().unwrap_or("_");
+            warnings.push(
+                SourceDiagnostic::warning(
+                    *span,
+                    eco_format!(
+                        "`{type_name}` does not pass the required \
+                         positional argument `{name}` of `{func_name}`"
+                    ),
+                )
+                .with_hint(eco_format!(
+                    "add a positional field to `{type_name}`"
+                )),
+            );
+        }
+
+        if has_sink {
+            return;
+        }
+
+        let extra = self
+            .params
+            .positional
+            .iter()
+            .skip(positional.len())
+            .map(|name| eco_format!("`{name}`"))
+            .collect::<Vec<_>>();
+        if !extra.is_empty() {
+            warnings.push(
+                SourceDiagnostic::warning(
+                    func_span,
+                    eco_format!(
+                        "`{type_name}` passes {} positional arguments, \
+                         but `{func_name}` only takes {}",
+                        self.params.positional.len(),
+                        positional.len()
+                    ),
+                )
+                .with_hint(eco_format!(
+                    "unexpected arguments: {}",
+                    extra.join(", ")
+                )),
+            );
+        }
+
+        for name in self.params.named.iter() {
+            if named.iter().any(|param| param == name) {
+                continue;
+            }
+
+            let mut warning = SourceDiagnostic::warning(
+                func_span,
+                eco_format!(
+                    "`{type_name}` passes the named argument `{name}`, \
+                     but `{func_name}` has no such parameter"
+                ),
+            );
+            if !named.is_empty() {
+                warning.hint(eco_format!(
+                    "available named parameters: {}",
+                    named.join(", ")
+                ));
+            }
+            warnings.push(warning);
+        }
+    }
+}
+
+/// The parameters of a closure bound with `let name(..) = ..` or
+/// `let name = (..) => ..`, read from its syntax.
+struct ClosureSignature {
+    span: Span,
+    positional: Vec<(Option<EcoString>, Span)>,
+    named: Vec<EcoString>,
+    has_sink: bool,
+}
+
+impl ClosureSignature {
+    /// The signature of the closure a binding was defined with.
+    ///
+    /// Imported bindings keep the span of their definition. Other
+    /// bindings, e.g. functions created with `.with(..)`, whose
+    /// pre-applied arguments are unknown, or native functions, are
+    /// skipped.
+    fn of(
+        binding: &Binding,
+        world: &dyn typst::World,
+    ) -> Option<Self> {
+        if !matches!(binding.read(), Value::Func(_)) {
+            return None;
+        }
+
+        let span = binding.span();
+        let source = world.source(span.id()?).ok()?;
+        let ident = source.find(span)?;
+        let parent = ident.parent()?;
+        let closure = match parent.cast::<ast::LetBinding>() {
+            Some(binding) => match binding.init()? {
+                ast::Expr::Closure(closure) => closure,
+                _ => return None,
+            },
+            None => parent.cast::<ast::Closure>()?,
+        };
+
+        let mut signature = Self {
+            span: closure.params().span(),
+            positional: Vec::new(),
+            named: Vec::new(),
+            has_sink: false,
+        };
+        for param in closure.params().children() {
+            match param {
+                ast::Param::Pos(pattern) => {
+                    let name = match pattern {
+                        ast::Pattern::Normal(ast::Expr::Ident(
+                            ident,
+                        )) => Some(ident.get().clone()),
+                        _ => None,
+                    };
+                    signature.positional.push((name, param.span()));
+                }
+                ast::Param::Named(named) => {
+                    signature.named.push(named.name().get().clone())
+                }
+                ast::Param::Spread(_) => signature.has_sink = true,
+            }
+        }
+        Some(signature)
+    }
+}
+
+/// Record the signature of `F` for the source of every changed `C`,
+/// and check it right away if the module is already evaluated.
+pub(crate) fn record_signatures<F, C>(
+    q_funcs: Query<&C, Changed<C>>,
+    world: VelystWorld,
+    modules: Res<VelystModules>,
+    mut signatures: ResMut<TypstFuncSignatures>,
+    mut diagnostics: MessageWriter<VelystDiagnostic>,
+) where
+    F: TypstFunc,
+    C: Component + AsAssetId<Asset = VelystSource>,
+{
+    for func in q_funcs.iter() {
+        let id = func.as_asset_id();
+        let Some(path) = world.asset_server.get_path(id) else {
+            continue;
+        };
+        if !signatures.insert::<F>(path.into_owned()) {
+            continue;
+        }
+
+        let (Some(module), Some(sig)) =
+            (modules.get(&id), FuncSignature::of::<F>())
+        else {
+            continue;
+        };
+        let mut warnings = Vec::new();
+        sig.check(module, &world, &mut warnings);
+        diagnostics.write_batch(warnings.iter().map(|diag| {
+            VelystDiagnostic::from_source(
+                &world,
+                DiagnosticStage::Eval,
+                diag,
+            )
+            .with_source(id)
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::func::{TypstFuncAppExt, VelystFunc};
+    use crate::renderer::WorldScene;
+    use crate::test_utils::{
+        frame_text, test_app, update_until, write_file,
+    };
+
+    #[derive(TypstFunc)]
+    #[typst(name = "card", crate = "crate")]
+    struct CardFunc {}
+
+    #[derive(TypstFunc)]
+    #[typst(name = "titled", crate = "crate")]
+    struct TitledFunc {}
+
+    /// Signature warnings sent during the last update.
+    fn warnings(app: &App) -> Vec<VelystDiagnostic> {
+        app.world()
+            .resource::<Messages<VelystDiagnostic>>()
+            .iter_current_update_messages()
+            .filter(|diag| {
+                !diag.is_error() && diag.message.contains("Func`")
+            })
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn check_only_own_source() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "a.typ", "#let card(title) = title");
+        write_file(dir.path(), "b.typ", "#let card() = [Card]");
+
+        let mut app = test_app(dir.path());
+        app.register_typst_func::<CardFunc>();
+        let asset_server = app.world().resource::<AssetServer>();
+        let a = asset_server.load::<VelystSource>("a.typ");
+        let b = asset_server.load::<VelystSource>("b.typ");
+        let entity = app
+            .world_mut()
+            .spawn((
+                VelystFunc::new(b, CardFunc {}),
+                WorldScene::default(),
+                Visibility::default(),
+            ))
+            .id();
+
+        let mut sent = Vec::new();
+        assert!(update_until(&mut app, |app| {
+            sent.extend(warnings(app));
+            frame_text(app, entity) == "Card"
+                && app
+                    .world()
+                    .resource::<VelystModules>()
+                    .contains_key(&a.id())
+        }));
+        assert!(sent.is_empty(), "{sent:?}");
+
+        // Checked right away, since `a.typ` is already evaluated.
+        app.world_mut().spawn((
+            VelystFunc::new(a.clone(), CardFunc {}),
+            Visibility::default(),
+        ));
+        app.update();
+        let sent = warnings(&app);
+        assert_eq!(sent.len(), 1, "{sent:?}");
+        assert_eq!(sent[0].source, Some(a.id()));
+        assert!(sent[0].message.contains("`title`"));
+    }
+
+    #[test]
+    fn read_params_from_definition() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "lib.typ",
+            "#let card(title, size: 1em) = text(size: size, title)",
+        );
+        write_file(
+            dir.path(),
+            "main.typ",
+            "#import \"lib.typ\": card\n\
+             #let titled = card.with(\"Title\")",
+        );
+
+        let mut app = test_app(dir.path());
+        app.register_typst_func::<CardFunc>()
+            .register_typst_func::<TitledFunc>();
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let entity = app
+            .world_mut()
+            .spawn((
+                VelystFunc::new(handle.clone(), TitledFunc {}),
+                WorldScene::default(),
+                Visibility::default(),
+            ))
+            .id();
+        app.world_mut().spawn((
+            VelystFunc::new(handle.clone(), CardFunc {}),
+            Visibility::default(),
+        ));
+
+        let mut sent = Vec::new();
+        assert!(update_until(&mut app, |app| {
+            sent.extend(warnings(app));
+            frame_text(app, entity) == "Title"
+        }));
+
+        // The imported closure is checked, the one with pre-applied
+        // arguments is skipped.
+        assert_eq!(sent.len(), 1, "{sent:?}");
+        assert!(sent[0].message.contains("CardFunc"));
+        assert!(sent[0].message.contains("`title`"));
+        assert_eq!(sent[0].source, Some(handle.id()));
+    }
+}
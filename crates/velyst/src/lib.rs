@@ -3,6 +3,10 @@
 use asset::TypstAssetPlugin;
 use bevy::prelude::*;
 use bevy::ui::UiSystems;
+use bridge::VelystBridgePlugin;
+use diag::VelystDiagnosticPlugin;
+use event::VelystEventPlugin;
+use func::VelystFuncPlugin;
 use renderer::VelystRendererPlugin;
 use world::VelystWorldPlugin;
 
@@ -14,23 +18,50 @@ pub use typst_element;
 pub mod prelude {
     pub use crate::VelystSet;
     pub use crate::asset::{VelystModules, VelystSource};
+    pub use crate::bridge::{
+        BevyColorExt, ImageExt, RectExt, TypstColorExt, ValExt,
+        Vec2Ext,
+    };
+    pub use crate::diag::{VelystDiagnostic, VelystErrors};
+    pub use crate::event::{
+        TypstEvent, TypstEventAppExt, VelystEvent,
+    };
     pub use crate::func::{
-        TypstFunc, TypstFuncAppExt, TypstValue, VelystContent,
-        VelystFunc, VelystSourceReady,
+        DynamicVelystFunc, ReflectTypstValue, TypstFunc,
+        TypstFuncAppExt, TypstValue, VelystContent, VelystFunc,
+        VelystFuncReflect, VelystSourceReady,
     };
+    pub use crate::native::TypstNativeFnAppExt;
+    pub use crate::overlay::VelystErrorOverlay;
+    pub use crate::query::{TypstQuery, TypstQueryAppExt};
     pub use crate::renderer::{
         UiScene, VelystFrame, VelystKanva, WorldScene,
     };
     pub use crate::typst_func;
-    pub use crate::world::VelystWorld;
+    pub use crate::world::fonts::{VelystFont, VelystFontConfig};
+    pub use crate::world::{
+        VelystEngineState, VelystInputActions, VelystInputs,
+        VelystWorld,
+    };
     pub use typst_element::prelude::*;
+    #[cfg(feature = "serde")]
+    pub use typst_element::serde::Serde;
 }
 
 pub mod asset;
+pub mod bridge;
+pub mod diag;
+pub mod event;
 pub mod func;
+pub mod native;
+pub mod overlay;
+pub mod query;
 pub mod renderer;
 pub mod world;
 
+#[cfg(test)]
+mod test_utils;
+
 /// Plugin for loading and rendering [Typst][typst] content.
 pub struct VelystPlugin;
 
@@ -49,9 +80,13 @@ impl Plugin for VelystPlugin {
         );
 
         app.add_plugins((
+            VelystDiagnosticPlugin,
             TypstAssetPlugin,
             VelystWorldPlugin,
+            VelystFuncPlugin,
             VelystRendererPlugin,
+            VelystEventPlugin,
+            VelystBridgePlugin,
         ));
     }
 }
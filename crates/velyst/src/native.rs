@@ -0,0 +1,349 @@
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bevy::prelude::*;
+use typst::diag::{At, HintedStrResult, SourceResult, bail};
+use typst::foundations::{
+    Args, Binding, CastInfo, FromValue, Func, IntoResult,
+    NativeFuncData, NativeFuncPtr, NativeParamInfo, Reflect, Scope,
+    Value,
+};
+use typst::syntax::Span;
+
+pub trait TypstNativeFnAppExt {
+    fn register_typst_native_fn<M>(
+        &mut self,
+        name: &'static str,
+        f: impl TypstNativeFn<M>,
+    ) -> &mut Self;
+}
+
+impl TypstNativeFnAppExt for App {
+    /// Register a Rust function that can be called from Typst by
+    /// name, e.g. `#item_name(id)`.
+    ///
+    /// Arguments are positional and converted with [`FromValue`],
+    /// while the return value is converted with [`IntoResult`], so
+    /// returning a [`StrResult`][typst::diag::StrResult] reports its
+    /// error at the call site.
+    ///
+    /// Results are memoized on the arguments, so the function must
+    /// be pure. Pass state it depends on, such as the locale, as an
+    /// argument.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bevy::prelude::*;
+    /// use velyst::prelude::*;
+    ///
+    /// App::new()
+    ///     .register_typst_native_fn("format_currency", |cents: i64| {
+    ///         format!("${}.{:02}", cents / 100, cents % 100)
+    ///     });
+    /// ```
+    fn register_typst_native_fn<M>(
+        &mut self,
+        name: &'static str,
+        f: impl TypstNativeFn<M>,
+    ) -> &mut Self {
+        let func = native_func(name, f);
+        self.world_mut()
+            .get_resource_or_init::<TypstNativeFns>()
+            .insert(name, func);
+        self
+    }
+}
+
+/// Rust functions registered with
+/// [`TypstNativeFnAppExt::register_typst_native_fn`], defined in the
+/// global scope of the [`TypstLibrary`][crate::world::TypstLibrary].
+#[derive(Resource, Default, Clone)]
+pub struct TypstNativeFns(Vec<(&'static str, Func)>);
+
+impl TypstNativeFns {
+    /// Add a function, replacing any previous function with the same
+    /// name.
+    pub fn insert(&mut self, name: &'static str, func: Func) {
+        self.0.retain(|(other, _)| *other != name);
+        self.0.push((name, func));
+    }
+
+    /// Whether no functions are registered.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Define every function in the given scope, shadowing any
+    /// built-in of the same name.
+    pub fn define(&self, scope: &mut Scope) {
+        for (name, func) in self.0.iter() {
+            scope.bind(
+                (*name).into(),
+                Binding::detached(func.clone()),
+            );
+        }
+    }
+}
+
+/// A Rust function callable from Typst with positional arguments.
+///
+/// Implemented for closures with up to 6 arguments that implement
+/// [`FromValue`], returning any type that implements
+/// [`IntoResult`].
+pub trait TypstNativeFn<M>: Send + Sync + 'static {
+    /// Describe the accepted arguments, in order.
+    fn params() -> Vec<CastInfo>;
+
+    /// Call the function with already counted arguments.
+    fn call(&self, args: &[Value]) -> SourceResult<Value>;
+}
+
+macro_rules! impl_typst_native_fn {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg,)*> TypstNativeFn<fn($($arg,)*) -> R> for F
+        where
+            F: Fn($($arg,)*) -> R + Send + Sync + 'static,
+            R: IntoResult,
+            $($arg: FromValue + Reflect,)*
+        {
+            fn params() -> Vec<CastInfo> {
+                vec![$($arg::input(),)*]
+            }
+
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn call(&self, args: &[Value]) -> SourceResult<Value> {
+                let mut args = args.iter().cloned();
+                $(
+                    let $arg = cast_arg::<$arg>(args.next())
+                        .at(Span::detached())?;
+                )*
+                self($($arg,)*).into_result(Span::detached())
+            }
+        }
+    };
+}
+
+impl_typst_native_fn!();
+impl_typst_native_fn!(A0);
+impl_typst_native_fn!(A0, A1);
+impl_typst_native_fn!(A0, A1, A2);
+impl_typst_native_fn!(A0, A1, A2, A3);
+impl_typst_native_fn!(A0, A1, A2, A3, A4);
+impl_typst_native_fn!(A0, A1, A2, A3, A4, A5);
+
+fn cast_arg<T: FromValue>(
+    value: Option<Value>,
+) -> HintedStrResult<T> {
+    T::from_value(value.unwrap_or_default())
+}
+
+/// A type-erased [`TypstNativeFn`], hashed by a unique id so that
+/// its calls can be memoized.
+#[derive(Clone)]
+struct ErasedNativeFn {
+    id: usize,
+    call: Arc<dyn Fn(&[Value]) -> SourceResult<Value> + Send + Sync>,
+}
+
+impl Hash for ErasedNativeFn {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+#[comemo::memoize]
+fn call_memoized(
+    f: &ErasedNativeFn,
+    args: &[Value],
+) -> SourceResult<Value> {
+    (f.call)(args)
+}
+
+/// Wrap a [`TypstNativeFn`] into a Typst [`Func`].
+///
+/// The function data is leaked, just like statically defined native
+/// functions live for the whole program.
+fn native_func<M, F: TypstNativeFn<M>>(
+    name: &'static str,
+    f: F,
+) -> Func {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let params = F::params();
+    let erased = ErasedNativeFn {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        call: Arc::new(move |args| f.call(args)),
+    };
+    let expected = params.len();
+
+    let function = move |_: &mut typst::engine::Engine,
+                         _: comemo::Tracked<
+        typst::foundations::Context,
+    >,
+                         args: &mut Args|
+          -> SourceResult<Value> {
+        let span = args.span;
+        let values = args.all::<Value>()?;
+        args.take().finish()?;
+        if values.len() != expected {
+            bail!(
+                span,
+                "{name} expects {expected} argument{}, found {}",
+                if expected == 1 { "" } else { "s" },
+                values.len()
+            );
+        }
+
+        call_memoized(&erased, &values).map_err(|mut errors| {
+            // Errors of the function itself point at the call site.
+            for error in errors.make_mut() {
+                if error.span.is_detached() {
+                    error.span = span.into();
+                }
+            }
+            errors
+        })
+    };
+
+    let params = params
+        .into_iter()
+        .enumerate()
+        .map(|(i, input)| NativeParamInfo {
+            name: format!("arg{i}").leak(),
+            docs: "",
+            def_site: None,
+            input,
+            default: None,
+            positional: true,
+            named: false,
+            variadic: false,
+            required: true,
+            settable: false,
+        })
+        .collect::<Vec<_>>();
+
+    let data = Box::leak(Box::new(NativeFuncData {
+        function: NativeFuncPtr(Box::leak(Box::new(function))),
+        name,
+        title: name,
+        docs: "",
+        def_site: None,
+        keywords: &[],
+        contextual: false,
+        scope: LazyLock::new(Box::leak(Box::new(Scope::new))),
+        params: LazyLock::new(Box::leak(Box::new(move || {
+            params.clone()
+        }))),
+        returns: LazyLock::new(Box::leak(Box::new(|| CastInfo::Any))),
+    }));
+
+    Func::from(&*data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::VelystSource;
+    use crate::diag::{DiagnosticStage, VelystErrors};
+    use crate::func::DynamicVelystFunc;
+    use crate::renderer::WorldScene;
+    use crate::test_utils::{
+        frame_text, test_app, update_until, write_file,
+    };
+
+    /// An app with `format_currency` registered, laying out the given
+    /// function of a source calling it.
+    fn setup(func: &'static str) -> (tempfile::TempDir, App, Entity) {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "main.typ",
+            "#let price() = format_currency(1234)\n\
+             #let wrong-type() = format_currency(\"1234\")\n\
+             #let wrong-count() = format_currency(12, 34)\n\
+             #let greeting() = greet()",
+        );
+
+        let mut app = test_app(dir.path());
+        app.register_typst_native_fn(
+            "format_currency",
+            |cents: i64| {
+                format!("${}.{:02}", cents / 100, cents % 100)
+            },
+        );
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let entity = app
+            .world_mut()
+            .spawn((
+                DynamicVelystFunc::new(handle, func),
+                WorldScene::default(),
+                Visibility::default(),
+            ))
+            .id();
+
+        (dir, app, entity)
+    }
+
+    /// The layout errors of an entity, once it has any.
+    fn layout_errors(app: &mut App, entity: Entity) -> Vec<String> {
+        assert!(update_until(app, |app| {
+            app.world().get::<VelystErrors>(entity).is_some()
+        }));
+        app.world()
+            .get::<VelystErrors>(entity)
+            .unwrap()
+            .stage(DiagnosticStage::Layout)
+            .iter()
+            .map(|diag| diag.message.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn call_from_typst() {
+        let (_dir, mut app, entity) = setup("price");
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "$12.34"
+        }));
+    }
+
+    #[test]
+    fn wrong_argument_type_is_an_error() {
+        let (_dir, mut app, entity) = setup("wrong-type");
+        let errors = layout_errors(&mut app, entity);
+        assert_eq!(errors, ["expected integer, found string"]);
+    }
+
+    #[test]
+    fn wrong_argument_count_is_an_error() {
+        let (_dir, mut app, entity) = setup("wrong-count");
+        let errors = layout_errors(&mut app, entity);
+        assert_eq!(
+            errors,
+            ["format_currency expects 1 argument, found 2"]
+        );
+    }
+
+    #[test]
+    fn registering_again_replaces() {
+        let (_dir, mut app, entity) = setup("greeting");
+        app.register_typst_native_fn("greet", || "Hello".to_string());
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "Hello"
+        }));
+
+        app.register_typst_native_fn("greet", || "Bye".to_string());
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "Bye"
+        }));
+        assert_eq!(
+            app.world().resource::<TypstNativeFns>().0.len(),
+            2
+        );
+    }
+}
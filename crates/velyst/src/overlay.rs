@@ -0,0 +1,306 @@
+use bevy::camera::primitives::Aabb;
+use bevy::prelude::*;
+use bevy::ui::ContentSize;
+use typst::foundations::{Array, Dict, IntoValue, Module, Value};
+use typst::layout::{Abs, Axes, Region, Size};
+use typst::syntax::{
+    FileId, RootedPath, Source, VirtualPath, VirtualRoot,
+};
+use typst_element::elem::FuncCall;
+use typst_element::prelude::*;
+
+use crate::VelystSet;
+use crate::diag::{VelystErrors, format_location};
+use crate::func::VelystContent;
+use crate::renderer::{UiScene, VelystFrame, WorldScene};
+use crate::world::VelystWorld;
+
+/// Opt-in plugin that replaces the [`VelystFrame`] of a failing
+/// [`UiScene`] or [`WorldScene`] entity with a panel describing its
+/// [`VelystErrors`].
+///
+/// The panel is cleared once the entity compiles and lays out
+/// successfully again.
+pub struct VelystErrorOverlay;
+
+impl Plugin for VelystErrorOverlay {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (show_error_overlay, clear_error_overlay)
+                .in_set(VelystSet::PostLayout),
+        );
+    }
+}
+
+/// Typst source of the error panel.
+const OVERLAY_SOURCE: &str = r##"
+#let overlay(errors) = block(
+  width: 320pt,
+  fill: rgb("#2d2a2e"),
+  stroke: 1pt + rgb("#ff6188"),
+  radius: 4pt,
+  inset: 8pt,
+)[
+  #set text(size: 10pt, fill: rgb("#fcfcfa"))
+  #set par(spacing: 6pt)
+  #for error in errors [
+    #text(fill: rgb("#ff6188"), weight: "bold")[error:] #error.message \
+    #text(size: 8pt, fill: rgb("#939293"))[#error.location]
+    #for hint in error.hints [
+      \ #text(size: 8pt, fill: rgb("#ffd866"))[hint: #hint]
+    ]
+
+  ]
+]
+"##;
+
+/// Replace the frame of entities with [`VelystErrors`] by the error
+/// panel.
+fn show_error_overlay(
+    world: VelystWorld,
+    mut module: Local<Option<Module>>,
+    mut q_errors: Query<
+        (
+            &VelystErrors,
+            &mut VelystFrame,
+            Option<&WorldScene>,
+            Option<&mut ContentSize>,
+            Option<&ComputedUiRenderTargetInfo>,
+            Option<&mut Aabb>,
+        ),
+        (
+            Or<(Changed<VelystErrors>, Changed<VelystFrame>)>,
+            Or<(With<UiScene>, With<WorldScene>)>,
+        ),
+    >,
+) {
+    // The module is evaluated with the current library, which may
+    // have changed since.
+    if world.library.is_changed() {
+        *module = None;
+    }
+
+    if q_errors.is_empty() {
+        return;
+    }
+
+    let module = match &*module {
+        Some(module) => module.clone(),
+        None => {
+            let source = Source::new(
+                FileId::new(RootedPath::new(
+                    VirtualRoot::Project,
+                    VirtualPath::new("/velyst/error_overlay.typ")
+                        .unwrap(),
+                )),
+                OVERLAY_SOURCE.into(),
+            );
+            let Some(overlay) = world.eval_source(&source).output
+            else {
+                error!("Unable to evaluate the error overlay!");
+                return;
+            };
+            module.insert(overlay).clone()
+        }
+    };
+
+    let Ok(overlay) = module.scope().get_func("overlay") else {
+        return;
+    };
+
+    for (
+        errors,
+        mut frame,
+        world_scene,
+        content_size,
+        target_info,
+        aabb,
+    ) in q_errors.iter_mut()
+    {
+        let errors = errors
+            .iter()
+            .map(|diag| {
+                let mut dict = Dict::new();
+                dict.insert(
+                    "message".into(),
+                    diag.message.clone().into_value(),
+                );
+                dict.insert(
+                    "location".into(),
+                    format_location(diag.file, diag.range)
+                        .into_value(),
+                );
+                dict.insert(
+                    "hints".into(),
+                    diag.hints
+                        .iter()
+                        .cloned()
+                        .map(IntoValue::into_value)
+                        .collect::<Array>()
+                        .into_value(),
+                );
+                dict.into_value()
+            })
+            .collect::<Array>();
+
+        let content =
+            overlay.clone().call(&[Value::Array(errors)]).pack();
+
+        let mut size = Size::splat(Abs::inf());
+        if let Some(world_scene) = world_scene {
+            if let Some(width) = world_scene.width {
+                size.x = Abs::pt(width);
+            }
+            if let Some(height) = world_scene.height {
+                size.y = Abs::pt(height);
+            }
+        }
+
+        let Some(overlay_frame) = world
+            .layout_frame(
+                &content,
+                Region::new(size, Axes::splat(false)),
+            )
+            .output
+        else {
+            continue;
+        };
+
+        let frame_size = overlay_frame.size();
+        let width = frame_size.x.to_pt() as f32;
+        let height = frame_size.y.to_pt() as f32;
+
+        if let Some(mut content_size) = content_size {
+            let scale_factor = target_info
+                .map(|info| info.scale_factor())
+                .unwrap_or(1.0);
+            *content_size = ContentSize::fixed_size(
+                Vec2::new(width, height) * scale_factor,
+            );
+        }
+
+        if let (Some(world_scene), Some(mut aabb)) =
+            (world_scene, aabb)
+        {
+            let anchor = world_scene.anchor;
+            *aabb = Aabb {
+                center: Vec3A::new(
+                    width * (0.5 - anchor.x),
+                    height * (anchor.y - 0.5),
+                    0.0,
+                ),
+                half_extents: Vec3A::new(
+                    width / 2.0,
+                    height / 2.0,
+                    0.0,
+                ),
+            };
+        }
+
+        frame.0 = Some(overlay_frame);
+    }
+}
+
+/// Relayout the original content once an entity has no more
+/// [`VelystErrors`].
+fn clear_error_overlay(
+    mut removed: RemovedComponents<VelystErrors>,
+    mut q_contents: Query<&mut VelystContent>,
+) {
+    for entity in removed.read() {
+        if let Ok(mut content) = q_contents.get_mut(entity) {
+            content.set_changed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use typst::foundations::Str;
+    use typst::layout::{Frame, FrameItem};
+    use typst::visualize::{Color, Paint};
+
+    use super::*;
+    use crate::asset::VelystSource;
+    use crate::func::DynamicVelystFunc;
+    use crate::native::TypstNativeFnAppExt;
+    use crate::test_utils::{
+        frame_text, test_app, update_until, write_file,
+    };
+
+    /// An app with the overlay laying out a failing function.
+    fn setup() -> (tempfile::TempDir, App, Entity) {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "main.typ", "#let broken() = missing");
+
+        let mut app = test_app(dir.path());
+        app.add_plugins(VelystErrorOverlay);
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let entity = app
+            .world_mut()
+            .spawn((
+                DynamicVelystFunc::new(handle, "broken"),
+                WorldScene::default(),
+                Visibility::default(),
+            ))
+            .id();
+
+        (dir, app, entity)
+    }
+
+    #[test]
+    fn overlay_shows_errors() {
+        let (_dir, mut app, entity) = setup();
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity)
+                .contains("unknown variable: missing")
+        }));
+        assert!(frame_text(&app, entity).starts_with("error:"));
+    }
+
+    #[test]
+    fn overlay_follows_library() {
+        /// Whether a shape in the frame is filled with `color`.
+        fn has_fill(frame: &Frame, color: &Color) -> bool {
+            frame.items().any(|(_, item)| match item {
+                FrameItem::Group(group) => {
+                    has_fill(&group.frame, color)
+                }
+                FrameItem::Shape(shape, _) => {
+                    matches!(
+                        &shape.fill,
+                        Some(Paint::Solid(fill)) if fill == color
+                    )
+                }
+                _ => false,
+            })
+        }
+
+        let (_dir, mut app, entity) = setup();
+        let frame_has_fill = |app: &App, color: &Color| {
+            app.world()
+                .get::<VelystFrame>(entity)
+                .and_then(|frame| frame.0.as_ref())
+                .is_some_and(|frame| has_fill(frame, color))
+        };
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity).starts_with("error:")
+        }));
+
+        // Shadow `rgb` in the library, which the overlay uses for its
+        // colors.
+        let color = Color::from_u8(1, 2, 3, 255);
+        assert!(!frame_has_fill(&app, &color));
+        let shadowed = color.clone();
+        app.register_typst_native_fn("rgb", move |_: Str| {
+            shadowed.clone()
+        });
+        assert!(update_until(&mut app, |app| {
+            frame_has_fill(app, &color)
+        }));
+    }
+}
@@ -0,0 +1,271 @@
+use bevy::asset::AsAssetId;
+use bevy::prelude::*;
+use ecow::eco_format;
+use typst::foundations::{Arg, Args, FromValue, Func, Str};
+use typst::syntax::{Span, Spanned};
+use typst_element::prelude::ScopeExt;
+
+use crate::VelystSet;
+use crate::asset::{
+    VelystModuleUpdated, VelystModules, VelystSource,
+};
+use crate::diag::{
+    DiagnosticStage, VelystDiagnostic, set_stage_errors,
+};
+use crate::func::signature::record_signatures;
+use crate::func::{TypstFunc, TypstFuncSignatures};
+use crate::world::VelystWorld;
+
+pub trait TypstQueryAppExt {
+    fn register_typst_query<F, T>(&mut self) -> &mut Self
+    where
+        F: TypstFunc,
+        T: FromValue + Send + Sync + 'static;
+}
+
+impl TypstQueryAppExt for App {
+    /// Register a [`TypstQuery<F, T>`] so that its result is updated
+    /// whenever the query or its module changes.
+    fn register_typst_query<F, T>(&mut self) -> &mut Self
+    where
+        F: TypstFunc,
+        T: FromValue + Send + Sync + 'static,
+    {
+        self.init_resource::<TypstFuncSignatures>().add_systems(
+            PostUpdate,
+            (
+                record_signatures::<F, TypstQuery<F, T>>,
+                run_typst_query::<F, T>,
+            )
+                .chain()
+                .in_set(VelystSet::Compile),
+        )
+    }
+}
+
+/// Calls a Typst function eagerly and stores its result as `T`,
+/// unlike [`VelystFunc`][crate::func::VelystFunc] which packs the
+/// call into content for layout.
+///
+/// Useful for reading values computed in Typst, such as balance
+/// tables or layout metrics. Register it with
+/// [`TypstQueryAppExt::register_typst_query`].
+///
+/// The query runs again whenever it is mutated or its module is
+/// re-evaluated, and the component is marked as changed whenever a
+/// new result is stored.
+#[derive(Component)]
+pub struct TypstQuery<F: TypstFunc, T: Send + Sync + 'static> {
+    pub handle: Handle<VelystSource>,
+    pub data: F,
+    result: Option<T>,
+}
+
+impl<F: TypstFunc, T: Send + Sync + 'static> TypstQuery<F, T> {
+    pub fn new(handle: Handle<VelystSource>, data: F) -> Self {
+        Self {
+            handle,
+            data,
+            result: None,
+        }
+    }
+
+    /// The result of the last successful call, if any.
+    pub fn result(&self) -> Option<&T> {
+        self.result.as_ref()
+    }
+}
+
+impl<F: TypstFunc, T: Send + Sync + 'static> AsAssetId
+    for TypstQuery<F, T>
+{
+    type Asset = VelystSource;
+
+    fn as_asset_id(&self) -> AssetId<Self::Asset> {
+        self.handle.id()
+    }
+}
+
+/// Call the function of every changed [`TypstQuery<F, T>`] and store
+/// its result.
+fn run_typst_query<F, T>(
+    mut commands: Commands,
+    mut q_queries: Query<(Entity, &mut TypstQuery<F, T>)>,
+    world: VelystWorld,
+    modules: Res<VelystModules>,
+    mut module_updates: MessageReader<VelystModuleUpdated>,
+    mut diagnostics: MessageWriter<VelystDiagnostic>,
+) where
+    F: TypstFunc,
+    T: FromValue + Send + Sync + 'static,
+{
+    let changed_assets: smallvec::SmallVec<
+        [AssetId<VelystSource>; 4],
+    > = module_updates.read().map(|e| e.id).collect();
+
+    for (entity, mut query) in q_queries.iter_mut() {
+        let needs_rerun = query.is_changed()
+            || changed_assets.contains(&query.handle.id());
+        if !needs_rerun {
+            continue;
+        }
+
+        let Some(module) = modules.get(&query.handle.id()) else {
+            continue;
+        };
+
+        let source_id = query.handle.id();
+        let mut stage_errors = Vec::new();
+        let mut report = |diag: VelystDiagnostic| {
+            let diag =
+                diag.with_source(source_id).with_entity(entity);
+            if diag.is_error() {
+                stage_errors.push(diag.clone());
+            }
+            diagnostics.write(diag);
+        };
+
+        match module.scope().get_path::<Func>(F::NAME) {
+            Ok(func) => {
+                let result =
+                    world.call_func(&func, func_args(&query.data));
+                for diag in result.diagnostics.iter() {
+                    report(VelystDiagnostic::from_source(
+                        &world,
+                        DiagnosticStage::Compile,
+                        diag,
+                    ));
+                }
+
+                if let Some(value) = result.output {
+                    match T::from_value(value) {
+                        Ok(value) => query.result = Some(value),
+                        Err(err) => report(VelystDiagnostic::error(
+                            DiagnosticStage::Compile,
+                            eco_format!(
+                                "Unable to cast the result of {}: {}",
+                                F::NAME,
+                                err.message()
+                            ),
+                        )),
+                    }
+                }
+            }
+            Err(err) => report(VelystDiagnostic::error(
+                DiagnosticStage::Compile,
+                eco_format!(
+                    "Unable to get typst function {}: {err}",
+                    F::NAME
+                ),
+            )),
+        }
+
+        set_stage_errors(
+            &mut commands,
+            entity,
+            DiagnosticStage::Compile,
+            stage_errors,
+        );
+    }
+}
+
+/// Collect the arguments of a [`TypstFunc`].
+fn func_args<F: TypstFunc>(data: &F) -> Args {
+    let mut positional_args = Vec::new();
+    let mut named_args = Vec::new();
+    data.apply_positional_args(&mut positional_args);
+    data.apply_named_args(&mut named_args);
+
+    let mut args = Args::new(Span::detached(), positional_args);
+    args.items
+        .extend(named_args.into_iter().map(|(name, value)| Arg {
+            span: Span::detached(),
+            name: Some(Str::from(name)),
+            value: Spanned::new(value, Span::detached()),
+        }));
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_app, update_until, write_file};
+
+    crate::typst_func!("labels", struct LabelsFunc {});
+
+    type LabelsQuery = TypstQuery<LabelsFunc, Vec<Str>>;
+
+    /// A document with labelled headings and a function listing
+    /// their labels.
+    fn document(labels: [&str; 2]) -> String {
+        format!(
+            "#let doc = [\n\
+             = Intro <{}>\n\
+             Unlabelled\n\
+             = Stats <{}>\n\
+             ]\n\
+             #let labels() = doc.children\
+             .filter(it => it.has(\"label\"))\
+             .map(it => str(it.label))",
+            labels[0], labels[1],
+        )
+    }
+
+    /// An app querying the labels of the document in `main.typ`.
+    fn setup(labels: [&str; 2]) -> (tempfile::TempDir, App, Entity) {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(dir.path(), "main.typ", &document(labels));
+
+        let mut app = test_app(dir.path());
+        app.register_typst_query::<LabelsFunc, Vec<Str>>();
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let entity = app
+            .world_mut()
+            .spawn(LabelsQuery::new(handle, LabelsFunc {}))
+            .id();
+
+        (dir, app, entity)
+    }
+
+    fn labels(app: &App, entity: Entity) -> Option<Vec<Str>> {
+        app.world()
+            .get::<LabelsQuery>(entity)
+            .unwrap()
+            .result()
+            .cloned()
+    }
+
+    #[test]
+    fn query_labelled_elements() {
+        let (_dir, mut app, entity) = setup(["intro", "stats"]);
+        assert!(update_until(&mut app, |app| {
+            labels(app, entity).is_some()
+        }));
+        assert_eq!(
+            labels(&app, entity).unwrap(),
+            ["intro", "stats"].map(Str::from)
+        );
+    }
+
+    #[test]
+    fn query_refreshes_with_source() {
+        let (dir, mut app, entity) = setup(["intro", "stats"]);
+        assert!(update_until(&mut app, |app| {
+            labels(app, entity).is_some()
+        }));
+
+        write_file(
+            dir.path(),
+            "main.typ",
+            &document(["intro", "credits"]),
+        );
+        app.world().resource::<AssetServer>().reload("main.typ");
+        assert!(update_until(&mut app, |app| {
+            labels(app, entity).unwrap()
+                == ["intro", "credits"].map(Str::from)
+        }));
+    }
+}
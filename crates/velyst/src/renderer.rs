@@ -4,13 +4,19 @@ use bevy::ui::ContentSize;
 use bevy_vello::prelude::*;
 use imaging_vello::VelloSceneSink;
 use kanva::prelude::*;
+use typst::diag::SourceDiagnostic;
+use typst::foundations::Content;
 use typst::layout::{Abs, Axes, Frame, Region, Size};
 use vello::Scene;
 use vello::peniko::kurbo::{Affine, Rect};
 
 use crate::VelystSet;
+use crate::asset::VelystDependencies;
+use crate::diag::{
+    DiagnosticStage, VelystDiagnostic, set_stage_errors,
+};
 use crate::func::VelystContent;
-use crate::world::VelystWorld;
+use crate::world::{Diagnosed, VelystWorld};
 
 pub struct VelystRendererPlugin;
 
@@ -40,9 +46,13 @@ impl Plugin for VelystRendererPlugin {
 
 /// Layout [`VelystContent`] into a [`VelystFrame`] in UI coordinates.
 fn layout_ui_content(
+    mut commands: Commands,
     world: VelystWorld,
+    mut dependencies: ResMut<VelystDependencies>,
+    mut diagnostics: MessageWriter<VelystDiagnostic>,
     mut q_contents: Query<
         (
+            Entity,
             &VelystContent,
             &mut VelystFrame,
             &Visibility,
@@ -62,6 +72,7 @@ fn layout_ui_content(
     >,
 ) {
     for (
+        entity,
         content,
         mut scene,
         viz,
@@ -91,10 +102,22 @@ fn layout_ui_content(
                 Abs::pt((computed_node.size.y / scale_factor) as f64);
         }
 
-        if let Some(frame) = world.layout_frame(
+        let result = layout_tracked(
+            &world,
+            &mut dependencies,
+            entity,
             &content.0,
             Region::new(size, Axes::splat(false)),
-        ) {
+        );
+        report_layout_diagnostics(
+            &mut commands,
+            &world,
+            &mut diagnostics,
+            entity,
+            &result.diagnostics,
+        );
+
+        if let Some(frame) = result.output {
             let frame_size = frame.size();
             let size = Vec2::new(
                 frame_size.x.to_pt() as f32,
@@ -109,9 +132,13 @@ fn layout_ui_content(
 /// Layout [`VelystContent`] into a [`VelystFrame`] in world
 /// coordinates.
 fn layout_world_content(
+    mut commands: Commands,
     world: VelystWorld,
+    mut dependencies: ResMut<VelystDependencies>,
+    mut diagnostics: MessageWriter<VelystDiagnostic>,
     mut q_contents: Query<
         (
+            Entity,
             &VelystContent,
             &mut VelystFrame,
             &WorldScene,
@@ -128,7 +155,7 @@ fn layout_world_content(
         ),
     >,
 ) {
-    for (content, mut scene, world_scene, viz, mut aabb) in
+    for (entity, content, mut scene, world_scene, viz, mut aabb) in
         q_contents.iter_mut()
     {
         if viz == Visibility::Hidden {
@@ -144,10 +171,22 @@ fn layout_world_content(
             size.y = Abs::pt(height);
         }
 
-        if let Some(frame) = world.layout_frame(
+        let result = layout_tracked(
+            &world,
+            &mut dependencies,
+            entity,
             &content.0,
             Region::new(size, Axes::splat(false)),
-        ) {
+        );
+        report_layout_diagnostics(
+            &mut commands,
+            &world,
+            &mut diagnostics,
+            entity,
+            &result.diagnostics,
+        );
+
+        if let Some(frame) = result.output {
             let frame_size = frame.size();
             let width = frame_size.x.to_pt() as f32;
             let height = frame_size.y.to_pt() as f32;
@@ -173,6 +212,59 @@ fn layout_world_content(
     }
 }
 
+/// Layout the content of an entity, recording the files read along
+/// the way in [`VelystDependencies`].
+fn layout_tracked(
+    world: &VelystWorld,
+    dependencies: &mut VelystDependencies,
+    entity: Entity,
+    content: &Content,
+    region: Region,
+) -> Diagnosed<Frame> {
+    // Reset the file slots so that changed files are read again and
+    // only the files read by this layout are marked as accessed.
+    world.reset_file_slots();
+
+    let result = world.layout_frame(content, region);
+    dependencies.track_layout(
+        entity,
+        content,
+        world.accessed_files(),
+        world,
+    );
+    result
+}
+
+/// Emit layout diagnostics of an entity and update its
+/// [`VelystErrors`].
+fn report_layout_diagnostics(
+    commands: &mut Commands,
+    world: &VelystWorld,
+    diagnostics: &mut MessageWriter<VelystDiagnostic>,
+    entity: Entity,
+    source_diagnostics: &[SourceDiagnostic],
+) {
+    let diags = source_diagnostics
+        .iter()
+        .map(|diag| {
+            VelystDiagnostic::from_source(
+                world,
+                DiagnosticStage::Layout,
+                diag,
+            )
+            .with_entity(entity)
+        })
+        .collect::<Vec<_>>();
+
+    set_stage_errors(
+        commands,
+        entity,
+        DiagnosticStage::Layout,
+        diags.iter().cloned(),
+    );
+    diagnostics.write_batch(diags);
+}
+
 /// Clear cache regularly to prevent memory build ups.
 fn comemo_evict() {
     typst::comemo::evict(4);
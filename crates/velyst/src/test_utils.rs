@@ -0,0 +1,81 @@
+//! Helpers shared by the unit tests.
+
+use std::path::Path;
+
+use bevy::prelude::*;
+use typst::layout::{Frame, FrameItem};
+
+use crate::VelystPlugin;
+use crate::renderer::VelystFrame;
+use crate::world::fonts::VelystFontConfig;
+
+/// Maximum number of updates run by [`update_until`].
+const MAX_UPDATES: usize = 10_000;
+
+/// A headless app running [`VelystPlugin`] with assets read from the
+/// given directory.
+///
+/// Only the embedded fonts are used, so that no background font scan
+/// lays out the content again at an arbitrary point.
+pub fn test_app(dir: &Path) -> App {
+    let mut app = App::new();
+    app.insert_resource(VelystFontConfig::embedded_only());
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin {
+            file_path: dir.to_string_lossy().into(),
+            ..default()
+        },
+        VelystPlugin,
+    ));
+    app
+}
+
+/// Write a file into the given directory, creating its parents.
+pub fn write_file(dir: &Path, path: &str, contents: &str) {
+    let path = dir.join(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+    std::fs::write(path, contents).unwrap();
+}
+
+/// Update the app until `done` returns `true`, giving up after a
+/// bounded number of updates. Returns whether `done` was reached.
+pub fn update_until(
+    app: &mut App,
+    mut done: impl FnMut(&mut App) -> bool,
+) -> bool {
+    for _ in 0..MAX_UPDATES {
+        app.update();
+        if done(app) {
+            return true;
+        }
+    }
+    false
+}
+
+/// The text laid out in the [`VelystFrame`] of an entity.
+pub fn frame_text(app: &App, entity: Entity) -> String {
+    let mut text = String::new();
+    if let Some(frame) = app
+        .world()
+        .get::<VelystFrame>(entity)
+        .and_then(|frame| frame.0.as_ref())
+    {
+        collect_text(frame, &mut text);
+    }
+    text
+}
+
+fn collect_text(frame: &Frame, text: &mut String) {
+    for (_, item) in frame.items() {
+        match item {
+            FrameItem::Group(group) => {
+                collect_text(&group.frame, text)
+            }
+            FrameItem::Text(item) => text.push_str(&item.text),
+            _ => {}
+        }
+    }
+}
@@ -1,45 +1,78 @@
-use std::io::Read;
-use std::path::{Path, PathBuf};
+use std::io;
+use std::path::Path;
 use std::sync::Mutex;
 use std::time::Duration;
 use std::{fs, mem};
 
-use bevy::asset::io::file::FileAssetReader;
+use crate::asset::{VelystFile, VelystSource};
+use crate::diag::VelystDiagnostic;
+use crate::native::TypstNativeFns;
+use bevy::asset::io::{AssetReaderError, AssetSourceId};
+use bevy::asset::{AssetPath, AssetServerMode};
 use bevy::ecs::system::SystemParam;
 use bevy::platform::collections::HashMap;
 use bevy::prelude::*;
+use bevy::tasks::block_on;
 use bevy::time::common_conditions::on_timer;
 use chrono::{DateTime, Datelike, Local, Timelike};
-use ecow::eco_format;
-use fonts::TypstFonts;
-use typst::comemo::Track;
-use typst::diag::{
-    FileError, FileResult, PackageError, Severity, SourceDiagnostic,
+use ecow::{EcoVec, eco_format};
+use fonts::{
+    TypstFonts, VelystFont, VelystFontConfig, VelystFontLoader,
+    VelystFontScan, VelystFontScanProgress,
 };
+use typst::comemo::{Constraint, Track};
+use typst::diag::{FileError, FileResult, SourceDiagnostic};
 use typst::engine::{Engine, Route, Sink, Traced};
 use typst::foundations::{
-    Bytes, Content, Datetime, Module, StyleChain,
+    Args, Binding, Bytes, Content, Context, Datetime, Dict, Func,
+    Module, Smart, StyleChain, Value,
 };
-use typst::introspection::EmptyIntrospector;
-use typst::layout::{Frame, Region};
-use typst::syntax::package::PackageSpec;
+use typst::introspection::{
+    EmptyIntrospector, Introspector, MAX_ITERS,
+};
+use typst::layout::{Abs, Frame, Region, Sides};
 use typst::syntax::{FileId, Source, VirtualRoot};
 use typst::text::{Font, FontBook};
 use typst::utils::{LazyHash, Protected};
-use typst::{Library, LibraryExt, World, WorldExt};
-use typst_layout::layout_frame;
+use typst::{Library, LibraryExt};
+use typst_layout::{Page, PagedIntrospector, layout_frame};
 
+pub mod engine;
 pub mod fonts;
+pub mod inputs;
+pub mod packages;
+
+pub use engine::{VelystEngineState, VelystInputActions};
+pub use inputs::VelystInputs;
+pub use packages::{TypstPackageDownload, TypstPackageRoots};
 
 pub struct VelystWorldPlugin;
 
 impl Plugin for VelystWorldPlugin {
     fn build(&self, app: &mut App) {
+        let font_config = app
+            .world_mut()
+            .get_resource_or_init::<VelystFontConfig>()
+            .clone();
+        if !app.world().contains_resource::<TypstFonts>() {
+            app.insert_resource(TypstFonts::immediate(&font_config));
+        }
+
         app.init_resource::<TypstRoot>()
             .init_resource::<TypstLibrary>()
-            .init_resource::<TypstFonts>()
+            .init_resource::<VelystInputs>()
+            .init_resource::<TypstNativeFns>()
+            .add_message::<VelystDiagnostic>()
+            .init_resource::<VelystFontScan>()
+            .add_message::<VelystFontScanProgress>()
+            .init_asset::<VelystFont>()
+            .init_asset_loader::<VelystFontLoader>()
             .init_resource::<TypstDateTime>()
+            .init_resource::<VelystEngineState>()
+            .init_resource::<VelystInputActions>()
+            .add_message::<engine::VelystEngineUpdated>()
             .init_resource::<TypstFileSlots>()
+            .init_resource::<TypstPackageRoots>()
             .init_resource::<TypstPackageDownload>();
 
         app.add_systems(
@@ -47,6 +80,35 @@ impl Plugin for VelystWorldPlugin {
             // Date time only goes down to the second,
             // so we don't need to update every frame.
             update_date_time.run_if(on_timer(Duration::from_secs(1))),
+        )
+        .add_systems(
+            PreUpdate,
+            (
+                engine::update_engine_state,
+                engine::detect_engine_changes,
+            )
+                .chain()
+                .after(bevy::input::InputSystems)
+                .before(crate::asset::eval_source),
+        )
+        .add_systems(
+            PreUpdate,
+            (
+                fonts::scan_fonts,
+                fonts::register_font_assets,
+                update_library,
+                fonts::invalidate_layout,
+            )
+                .chain(),
+        )
+        .add_systems(
+            PostUpdate,
+            (
+                inputs::update_entity_libraries
+                    .before(crate::VelystSet::Compile),
+                fonts::warn_font_fallback
+                    .in_set(crate::VelystSet::PostLayout),
+            ),
         );
     }
 }
@@ -56,16 +118,13 @@ fn update_date_time(mut date_time: ResMut<TypstDateTime>) {
     date_time.0 = chrono::Local::now();
 }
 
-/// The root folder of the Typst assets.
-#[derive(Resource, Deref, DerefMut)]
-pub struct TypstRoot(PathBuf);
-
-impl Default for TypstRoot {
-    fn default() -> Self {
-        let base = FileAssetReader::get_base_path();
-        Self(base.join(AssetPlugin::default().file_path))
-    }
-}
+/// The [`AssetSource`][bevy::asset::io::AssetSource] that project
+/// files are read from. Defaults to the default asset source.
+///
+/// Typst paths are resolved relative to the root of this source, e.g.
+/// `#import "/ui/button.typ"` reads `ui/button.typ` from it.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct TypstRoot(pub AssetSourceId<'static>);
 
 /// Typst's standard library.
 #[derive(Resource, Deref, DerefMut)]
@@ -77,6 +136,60 @@ impl Default for TypstLibrary {
     }
 }
 
+/// Everything the [`TypstLibrary`] is built from.
+#[derive(SystemParam)]
+pub struct LibrarySettings<'w> {
+    pub inputs: Res<'w, VelystInputs>,
+    pub font_config: Res<'w, VelystFontConfig>,
+    pub native_fns: Res<'w, TypstNativeFns>,
+}
+
+impl LibrarySettings<'_> {
+    /// Whether any of the settings changed since the last run.
+    pub fn is_changed(&self) -> bool {
+        self.inputs.is_changed()
+            || self.font_config.is_changed()
+            || self.native_fns.is_changed()
+    }
+
+    /// Whether the settings match Typst's default library.
+    fn is_default(&self) -> bool {
+        self.inputs.is_empty()
+            && self.font_config.families.is_empty()
+            && self.native_fns.is_empty()
+    }
+
+    /// Build a library with the given inputs, the configured default
+    /// font families and the registered native functions.
+    pub fn build(&self, inputs: &Dict) -> Library {
+        let mut library =
+            Library::builder().with_inputs(inputs.clone()).build();
+        fonts::set_font_families(&mut library, &self.font_config);
+        if !self.native_fns.is_empty() {
+            self.native_fns.define(library.global.scope_mut());
+            library.std = Binding::detached(library.global.clone());
+        }
+        library
+    }
+}
+
+/// Rebuild the [`TypstLibrary`] when its [`LibrarySettings`] change.
+fn update_library(
+    settings: LibrarySettings,
+    mut library: ResMut<TypstLibrary>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    // The default library is already in place on startup.
+    if library.is_added() && settings.is_default() {
+        return;
+    }
+
+    **library = LazyHash::new(settings.build(&settings.inputs));
+}
+
 /// The current datetime if requested. This is stored here to ensure
 /// it is always the same within one frame. Reset between frames.
 #[derive(Resource, Deref, DerefMut)]
@@ -95,141 +208,227 @@ pub type FileSlots = HashMap<FileId, FileSlot>;
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct TypstFileSlots(Mutex<FileSlots>);
 
-/// Controls whether typst packages can be downloaded from the
-/// internet, and where they are cached locally.
+/// The output of [`VelystWorld::eval_source`] or
+/// [`VelystWorld::layout_frame`] alongside every diagnostic emitted
+/// along the way.
 ///
-/// Enabled by default in debug builds, disabled in release builds.
-/// Change this resource at runtime to override the default behavior.
-#[derive(Resource)]
-pub struct TypstPackageDownload {
-    pub enabled: bool,
-    /// Local directory where downloaded packages are cached.
-    /// Defaults to `assets/typst_packages`.
-    pub cache_dir: PathBuf,
-}
-
-impl Default for TypstPackageDownload {
-    fn default() -> Self {
-        let base = FileAssetReader::get_base_path();
-        Self {
-            enabled: cfg!(debug_assertions),
-            cache_dir: base
-                .join(AssetPlugin::default().file_path)
-                .join("typst_packages"),
-        }
-    }
+/// `output` is `None` if there were fatal errors.
+pub struct Diagnosed<T> {
+    pub output: Option<T>,
+    pub diagnostics: EcoVec<SourceDiagnostic>,
 }
 
 #[derive(SystemParam)]
 pub struct VelystWorld<'w> {
+    pub asset_server: Res<'w, AssetServer>,
     pub root: Res<'w, TypstRoot>,
+    pub sources: Res<'w, Assets<VelystSource>>,
+    pub files: Res<'w, Assets<VelystFile>>,
     pub library: Res<'w, TypstLibrary>,
     pub fonts: Res<'w, TypstFonts>,
     pub date_time: Res<'w, TypstDateTime>,
+    pub engine: Res<'w, VelystEngineState>,
     pub file_slots: Res<'w, TypstFileSlots>,
+    pub package_roots: Res<'w, TypstPackageRoots>,
     pub package_download: Res<'w, TypstPackageDownload>,
 }
 
 impl VelystWorld<'_> {
-    pub fn eval_source(&self, source: &Source) -> Option<Module> {
-        // Typst world
-        let world: &dyn typst::World = self;
-        let mut sink = Sink::new();
+    /// Evaluate a source file into a module.
+    pub fn eval_source(&self, source: &Source) -> Diagnosed<Module> {
+        eval(self, source)
+    }
 
-        // Try to evaluate the source file into a module.
-        let module = typst_eval::eval(
-            world.track(),
-            world.library(),
-            Traced::default().track(),
-            sink.track_mut(),
-            Route::default().track(),
+    /// Evaluate a source file into a module with a different
+    /// library, e.g. one built with per-entity [`VelystInputs`].
+    pub fn eval_source_with(
+        &self,
+        source: &Source,
+        library: &LazyHash<Library>,
+    ) -> Diagnosed<Module> {
+        eval(
+            &LibraryWorld {
+                world: self,
+                library,
+            },
             source,
-        );
+        )
+    }
 
-        match module {
-            Ok(module) => {
-                for warning in sink.warnings() {
-                    log_diagnostic(self, warning);
-                }
+    /// Call a function eagerly and return its value, e.g. to read
+    /// back dictionaries or numbers computed in Typst.
+    ///
+    /// The call happens outside of layout, so introspection (e.g.
+    /// `query()` or `counter()`) sees an empty document.
+    pub fn call_func(
+        &self,
+        func: &Func,
+        args: Args,
+    ) -> Diagnosed<Value> {
+        let world: &dyn typst::World = self;
+        let introspector = EmptyIntrospector;
+        let traced = Traced::default();
+        let mut sink = Sink::new();
 
-                Some(module)
-            }
+        let mut engine = Engine {
+            world: world.track(),
+            library: world.library(),
+            introspector: Protected::new(
+                (&introspector as &dyn Introspector).track(),
+            ),
+            traced: traced.track(),
+            sink: sink.track_mut(),
+            route: Route::default(),
+        };
+        let value =
+            func.call(&mut engine, Context::none().track(), args);
+
+        match value {
+            Ok(value) => Diagnosed {
+                output: Some(value),
+                diagnostics: sink.warnings(),
+            },
             Err(errors) => {
-                error!("Evaluation failed for {:?}!", source.id());
-                for error in errors {
-                    log_diagnostic(self, error);
+                let mut diagnostics = sink.warnings();
+                diagnostics.extend(errors);
+                Diagnosed {
+                    output: None,
+                    diagnostics,
                 }
-
-                None
             }
         }
     }
 
+    /// Layout content into a single frame within the given region.
     pub fn layout_frame(
         &self,
         content: &Content,
         region: Region,
-    ) -> Option<Frame> {
-        let world: &dyn typst::World = self;
+    ) -> Diagnosed<Frame> {
+        let world = LayoutWorld(self);
+        let world: &dyn typst::World = &world;
         let styles = StyleChain::new(&world.library().styles);
 
-        let introspector = EmptyIntrospector;
+        let empty_introspector = EmptyIntrospector;
+        let mut history: Vec<PagedIntrospector> =
+            Vec::with_capacity(MAX_ITERS - 1);
 
         let traced = Traced::default();
         let mut sink = Sink::new();
 
         // Relayout until all introspections stabilize.
         // If that doesn't happen within five attempts, we give up.
-        // TODO: Implement the loop to support counter & states.
-        let frame = {
-            // Clear delayed errors.
-            sink.delayed();
-
+        let frame = loop {
+            let introspector = history
+                .last()
+                .map(|i| i as &dyn Introspector)
+                .unwrap_or(&empty_introspector);
+            let constraint = Constraint::new();
+
+            let mut subsink = Sink::new();
             let mut engine = Engine {
                 world: world.track(),
                 library: world.library(),
-                introspector: Protected::new(introspector.track()),
+                introspector: Protected::new(
+                    introspector.track_with(&constraint),
+                ),
                 traced: traced.track(),
-                sink: sink.track_mut(),
+                sink: subsink.track_mut(),
                 route: Route::default(),
             };
 
             let locator = typst::introspection::Locator::root();
 
             // Layout!
-            layout_frame(
+            let frame = match layout_frame(
                 &mut engine,
                 content,
                 locator,
                 styles,
                 region,
-            )
+            ) {
+                Ok(frame) => frame,
+                Err(errors) => {
+                    sink.extend_from_sink(subsink);
+                    break Err(errors);
+                }
+            };
+
+            let frame_introspector = introspect_frame(&frame);
+
+            if constraint
+                .validate(&frame_introspector as &dyn Introspector)
+            {
+                sink.extend_from_sink(subsink);
+                break Ok(frame);
+            }
+
+            if history.len() == MAX_ITERS - 1 {
+                let mut introspectors = [&empty_introspector
+                    as &dyn Introspector;
+                    MAX_ITERS + 1];
+                for i in 1..MAX_ITERS {
+                    introspectors[i] = &history[i - 1];
+                }
+                introspectors[MAX_ITERS] = &frame_introspector;
+
+                let warnings = typst::introspection::analyze(
+                    world.track(),
+                    introspectors,
+                    subsink.introspections(),
+                );
+
+                sink.extend_from_sink(subsink);
+                for warning in warnings {
+                    sink.warn(warning);
+                }
+                break Ok(frame);
+            }
+
+            history.push(frame_introspector);
         };
 
-        // Log delayed errors.
-        for delay in sink.delayed() {
-            log_diagnostic(self, delay);
-        }
+        // Delayed errors are reported without discarding the frame.
+        let mut diagnostics = sink.delayed();
 
         match frame {
             Ok(frame) => {
-                for warning in sink.warnings() {
-                    log_diagnostic(self, warning);
+                diagnostics.extend(sink.warnings());
+                Diagnosed {
+                    output: Some(frame),
+                    diagnostics,
                 }
-
-                Some(frame)
             }
             Err(errors) => {
-                error!("Layout failed!");
-                for error in errors {
-                    log_diagnostic(self, error);
+                diagnostics.extend(sink.warnings());
+                diagnostics.extend(errors);
+                Diagnosed {
+                    output: None,
+                    diagnostics,
                 }
-
-                None
             }
         }
     }
 
+    /// Reset the accessed state of all file slots.
+    pub fn reset_file_slots(&self) {
+        let mut file_slots = self.file_slots.lock().unwrap();
+        for slot in file_slots.values_mut() {
+            slot.reset();
+        }
+    }
+
+    /// File ids that were accessed since the last
+    /// [`Self::reset_file_slots`].
+    pub fn accessed_files(&self) -> Vec<FileId> {
+        let file_slots = self.file_slots.lock().unwrap();
+        file_slots
+            .iter()
+            .filter(|(_, slot)| slot.accessed())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
     /// Access the canonical slot for the given file id.
     fn slot<F, T>(&self, id: FileId, f: F) -> T
     where
@@ -238,6 +437,115 @@ impl VelystWorld<'_> {
         let mut file_slots = self.file_slots.lock().unwrap();
         f(file_slots.entry(id).or_insert_with(|| FileSlot::new(id)))
     }
+
+    /// The asset path of a project file within the [`TypstRoot`]
+    /// asset source.
+    pub(crate) fn asset_path(
+        &self,
+        id: FileId,
+    ) -> AssetPath<'static> {
+        AssetPath::from_path_buf(
+            id.vpath().get_without_slash().into(),
+        )
+        .with_source(self.root.clone_owned())
+    }
+
+    /// Read the raw contents of a file.
+    ///
+    /// Project files are served from their loaded [`VelystSource`] or
+    /// [`VelystFile`] asset. Files that are not loaded yet are read
+    /// through the [`TypstRoot`] asset source if `blocking`, or
+    /// reported as still loading otherwise. The engine package is
+    /// served from [`VelystEngineState`] and other package files are
+    /// read from the local package cache.
+    fn read(
+        &self,
+        id: FileId,
+        blocking: bool,
+    ) -> FileResult<Vec<u8>> {
+        if let VirtualRoot::Package(spec) = id.root() {
+            if let Some(result) = engine::check_engine_package(spec) {
+                return result.and_then(|_| self.engine.read(id));
+            }
+
+            let root = packages::prepare_package(
+                spec,
+                &self.package_roots,
+                &self.package_download,
+            )?;
+            // Join the path to the root. If it tries to escape, deny
+            // access. Note: It can still escape via symlinks.
+            let path = id
+                .vpath()
+                .realize(&root)
+                .map_err(|_| FileError::AccessDenied)?;
+            return read(&path);
+        }
+
+        let path = self.asset_path(id);
+        if let Some(data) = self.loaded(&path) {
+            return Ok(data);
+        }
+        if !blocking {
+            // The file is loaded once it is watched as a dependency,
+            // which lays the content out again.
+            return Err(FileError::Other(Some(eco_format!(
+                "{} is still loading",
+                path.path().display()
+            ))));
+        }
+
+        let source =
+            self.asset_server.get_source(&**self.root).map_err(
+                |e| FileError::Other(Some(eco_format!("{e}"))),
+            )?;
+        let reader = match self.asset_server.mode() {
+            AssetServerMode::Processed => {
+                source.processed_reader().map_err(|e| {
+                    FileError::Other(Some(eco_format!("{e}")))
+                })?
+            }
+            AssetServerMode::Unprocessed => source.reader(),
+        };
+
+        let path = Path::new(id.vpath().get_without_slash());
+        block_on(async {
+            let mut reader =
+                reader.read(path).await.map_err(|e| match e {
+                    AssetReaderError::NotFound(path) => {
+                        FileError::NotFound(path)
+                    }
+                    AssetReaderError::Io(e) => FileError::from_io(
+                        io::Error::new(e.kind(), e.to_string()),
+                        path,
+                    ),
+                    e => FileError::Other(Some(eco_format!("{e}"))),
+                })?;
+
+            let mut data = Vec::new();
+            reader
+                .read_to_end(&mut data)
+                .await
+                .map_err(|e| FileError::from_io(e, path))?;
+            Ok(data)
+        })
+    }
+
+    /// The contents of a project file whose asset is already loaded.
+    fn loaded(&self, path: &AssetPath) -> Option<Vec<u8>> {
+        if let Some(source) = self
+            .asset_server
+            .get_handle::<VelystSource>(path)
+            .and_then(|handle| self.sources.get(&handle))
+        {
+            return Some(source.text().as_bytes().to_vec());
+        }
+
+        self.asset_server
+            .get_handle::<VelystFile>(path)
+            .and_then(|handle| self.files.get(&handle))
+            .map(|file| file.to_vec())
+    }
 }
 
 impl typst::World for VelystWorld<'_> {
@@ -254,15 +562,11 @@ impl typst::World for VelystWorld<'_> {
     }
 
     fn source(&self, id: FileId) -> FileResult<Source> {
-        self.slot(id, |slot| {
-            slot.source(&self.root, &self.package_download)
-        })
+        self.slot(id, |slot| slot.source(|| self.read(id, true)))
     }
 
     fn file(&self, id: FileId) -> FileResult<Bytes> {
-        self.slot(id, |slot| {
-            slot.file(&self.root, &self.package_download)
-        })
+        self.slot(id, |slot| slot.file(|| self.read(id, true)))
     }
 
     fn font(&self, index: usize) -> Option<Font> {
@@ -294,6 +598,116 @@ impl typst::World for VelystWorld<'_> {
     }
 }
 
+/// A [`VelystWorld`] with its library replaced.
+struct LibraryWorld<'a, 'w> {
+    world: &'a VelystWorld<'w>,
+    library: &'a LazyHash<Library>,
+}
+
+impl typst::World for LibraryWorld<'_, '_> {
+    fn library(&self) -> &LazyHash<Library> {
+        self.library
+    }
+
+    fn book(&self) -> &LazyHash<FontBook> {
+        self.world.book()
+    }
+
+    fn main(&self) -> FileId {
+        self.world.main()
+    }
+
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        self.world.source(id)
+    }
+
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        self.world.file(id)
+    }
+
+    fn font(&self, index: usize) -> Option<Font> {
+        self.world.font(index)
+    }
+
+    fn today(
+        &self,
+        offset: Option<typst::foundations::Duration>,
+    ) -> Option<Datetime> {
+        self.world.today(offset)
+    }
+}
+
+/// A [`VelystWorld`] that never blocks on reading files, used while
+/// laying out content.
+struct LayoutWorld<'a, 'w>(&'a VelystWorld<'w>);
+
+impl typst::World for LayoutWorld<'_, '_> {
+    fn library(&self) -> &LazyHash<Library> {
+        self.0.library()
+    }
+
+    fn book(&self) -> &LazyHash<FontBook> {
+        self.0.book()
+    }
+
+    fn main(&self) -> FileId {
+        self.0.main()
+    }
+
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        self.0
+            .slot(id, |slot| slot.source(|| self.0.read(id, false)))
+    }
+
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        self.0.slot(id, |slot| slot.file(|| self.0.read(id, false)))
+    }
+
+    fn font(&self, index: usize) -> Option<Font> {
+        self.0.font(index)
+    }
+
+    fn today(
+        &self,
+        offset: Option<typst::foundations::Duration>,
+    ) -> Option<Datetime> {
+        self.0.today(offset)
+    }
+}
+
+/// Evaluate a source file into a module within the given world.
+fn eval(
+    world: &dyn typst::World,
+    source: &Source,
+) -> Diagnosed<Module> {
+    let mut sink = Sink::new();
+
+    // Try to evaluate the source file into a module.
+    let module = typst_eval::eval(
+        world.track(),
+        world.library(),
+        Traced::default().track(),
+        sink.track_mut(),
+        Route::default().track(),
+        source,
+    );
+
+    match module {
+        Ok(module) => Diagnosed {
+            output: Some(module),
+            diagnostics: sink.warnings(),
+        },
+        Err(errors) => {
+            let mut diagnostics = sink.warnings();
+            diagnostics.extend(errors);
+            Diagnosed {
+                output: None,
+                diagnostics,
+            }
+        }
+    }
+}
+
 /// Holds the processed data for a file ID.
 ///
 /// Both fields can be populated if the file is both imported and
@@ -320,33 +734,25 @@ impl FileSlot {
     /// Retrieve the source for this file.
     fn source(
         &mut self,
-        project_root: &Path,
-        download: &TypstPackageDownload,
+        read: impl FnOnce() -> FileResult<Vec<u8>>,
     ) -> FileResult<Source> {
-        self.source.get_or_init(
-            || system_path(project_root, self.id, download),
-            |data, prev| {
-                let text = decode_utf8(&data)?;
-                if let Some(mut prev) = prev {
-                    prev.replace(text);
-                    Ok(prev)
-                } else {
-                    Ok(Source::new(self.id, text.into()))
-                }
-            },
-        )
+        self.source.get_or_init(read, |data, prev| {
+            let text = decode_utf8(&data)?;
+            if let Some(mut prev) = prev {
+                prev.replace(text);
+                Ok(prev)
+            } else {
+                Ok(Source::new(self.id, text.into()))
+            }
+        })
     }
 
     /// Retrieve the file's bytes.
     fn file(
         &mut self,
-        project_root: &Path,
-        download: &TypstPackageDownload,
+        read: impl FnOnce() -> FileResult<Vec<u8>>,
     ) -> FileResult<Bytes> {
-        self.file.get_or_init(
-            || system_path(project_root, self.id, download),
-            |data, _| Ok(Bytes::new(data)),
-        )
+        self.file.get_or_init(read, |data, _| Ok(Bytes::new(data)))
     }
 
     /// Reset the accessed state of the file.
@@ -354,6 +760,12 @@ impl FileSlot {
         self.source.reset();
         self.file.reset();
     }
+
+    /// Whether the file was read as a source or as bytes since the
+    /// last [reset][Self::reset].
+    pub fn accessed(&self) -> bool {
+        self.source.accessed || self.file.accessed
+    }
 }
 
 /// Lazily processes data for a file.
@@ -385,7 +797,7 @@ impl<T: Clone> SlotCell<T> {
     /// Gets the contents of the cell or initialize them.
     fn get_or_init(
         &mut self,
-        path: impl FnOnce() -> FileResult<PathBuf>,
+        read: impl FnOnce() -> FileResult<Vec<u8>>,
         f: impl FnOnce(Vec<u8>, Option<T>) -> FileResult<T>,
     ) -> FileResult<T> {
         // If we accessed the file already in this compilation,
@@ -397,7 +809,7 @@ impl<T: Clone> SlotCell<T> {
         }
 
         // Read and hash the file.
-        let result = path().and_then(|p| read(&p));
+        let result = read();
         let fingerprint = typst::utils::hash128(&result);
 
         // If the file contents didn't change, yield the old processed
@@ -417,102 +829,20 @@ impl<T: Clone> SlotCell<T> {
     }
 }
 
-/// Resolves the path of a file id on the system, downloading a
-/// package if necessary.
-fn system_path(
-    project_root: &Path,
-    id: FileId,
-    download: &TypstPackageDownload,
-) -> FileResult<PathBuf> {
-    let root = if let VirtualRoot::Package(spec) = id.root() {
-        prepare_package(spec, download)?
-    } else {
-        project_root.to_path_buf()
-    };
-
-    // Join the path to the root. If it tries to escape, deny
-    // access. Note: It can still escape via symlinks.
-    id.vpath()
-        .realize(&root)
-        .map_err(|_| FileError::AccessDenied)
-}
-
-/// Returns the local cache directory for a package, downloading it
-/// first if it is not already present and downloading is enabled.
-fn prepare_package(
-    spec: &PackageSpec,
-    download: &TypstPackageDownload,
-) -> FileResult<PathBuf> {
-    let package_dir = download
-        .cache_dir
-        .join(spec.namespace.as_str())
-        .join(spec.name.as_str())
-        .join(spec.version.to_string());
-
-    if package_dir.exists() {
-        return Ok(package_dir);
-    }
-
-    if !download.enabled {
-        return Err(FileError::Package(PackageError::Other(Some(
-            eco_format!(
-                "package downloading is disabled; \
-             enable it via `TypstPackageDownload` or pre-cache {spec} \
-             in \"{}\"",
-                download.cache_dir.display()
-            ),
-        ))));
-    }
-
-    download_package(spec, &package_dir)?;
-    Ok(package_dir)
-}
-
-/// Downloads a package from `packages.typst.org` and extracts it into
-/// `dest`.
-fn download_package(
-    spec: &PackageSpec,
-    dest: &Path,
-) -> FileResult<PathBuf> {
-    let url = format!(
-        "https://packages.typst.org/{}/{}-{}.tar.gz",
-        spec.namespace, spec.name, spec.version,
-    );
-
-    info!("Downloading typst package {spec} from {url}");
-
-    let response = ureq::get(&url).call().map_err(|e| {
-        FileError::Package(PackageError::NetworkFailed(Some(
-            eco_format!("{e}"),
-        )))
-    })?;
-
-    if let Some(parent) = dest.parent() {
-        fs::create_dir_all(parent).map_err(|e| {
-            FileError::Package(PackageError::Other(Some(
-                eco_format!("{e}"),
-            )))
-        })?;
-    }
-
-    let mut body = Vec::new();
-    response.into_reader().read_to_end(&mut body).map_err(|e| {
-        FileError::Package(PackageError::NetworkFailed(Some(
-            eco_format!("{e}"),
-        )))
-    })?;
-
-    let decoder = flate2::read::GzDecoder::new(body.as_slice());
-    tar::Archive::new(decoder).unpack(dest).map_err(|e| {
-        FileError::Package(PackageError::MalformedArchive(Some(
-            eco_format!("{e}"),
-        )))
-    })?;
-
-    Ok(dest.to_path_buf())
+/// Creates an introspector for a single laid-out frame by treating
+/// it as the only page of a document.
+fn introspect_frame(frame: &Frame) -> PagedIntrospector {
+    PagedIntrospector::new(&[Page {
+        frame: frame.clone(),
+        bleed: Sides::splat(Abs::zero()),
+        fill: Smart::Auto,
+        numbering: None,
+        supplement: Content::empty(),
+        number: 1,
+    }])
 }
 
-/// Read a file.
+/// Read a file from the local file system.
 fn read(path: &Path) -> FileResult<Vec<u8>> {
     let f = |e| FileError::from_io(e, path);
     if fs::metadata(path).map_err(f)?.is_dir() {
@@ -530,45 +860,47 @@ fn decode_utf8(buf: &[u8]) -> FileResult<&str> {
     )?)
 }
 
-fn log_diagnostic(world: &VelystWorld, diagnostic: SourceDiagnostic) {
-    let mut log_msg = String::new();
-    log_msg.push('\n');
-    log_msg.push_str(&diagnostic.message);
-    log_msg.push('\n');
-
-    let location = diagnostic.span.id().map(|id| {
-        let line_col = world
-            .source(id)
-            .ok()
-            .zip(world.range(diagnostic.span))
-            .and_then(|(source, range)| {
-                source.lines().byte_to_line_column(range.start)
-            });
-        match line_col {
-            Some((line, col)) => {
-                format!("In file: {:?}:{}:{}", id, line + 1, col + 1)
-            }
-            None => format!("In file: {:?}", id),
-        }
-    });
-    log_msg.push_str(
-        &location.unwrap_or_else(|| "In file: <unknown>".to_string()),
-    );
-    log_msg.push('\n');
-    log_msg.push_str(&format!("Trace: {:?}", diagnostic.trace));
-    log_msg.push('\n');
-    log_msg.push_str(&format!(
-        "Hints: {}",
-        diagnostic
-            .hints
-            .iter()
-            .map(|h| h.v.as_str())
-            .collect::<Vec<_>>()
-            .join("\n")
-    ));
-
-    match diagnostic.severity {
-        Severity::Error => error!("{log_msg}"),
-        Severity::Warning => warn!("{log_msg}"),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::func::DynamicVelystFunc;
+    use crate::renderer::WorldScene;
+    use crate::test_utils::{
+        frame_text, test_app, update_until, write_file,
+    };
+
+    #[test]
+    fn read_from_loaded_asset() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "main.typ",
+            "#let data(path) = read(path)",
+        );
+        write_file(dir.path(), "data.txt", "cached");
+
+        let mut app = test_app(dir.path());
+        let asset_server = app.world().resource::<AssetServer>();
+        let source = asset_server.load::<VelystSource>("main.typ");
+        let file = asset_server.load::<VelystFile>("data.txt");
+        assert!(update_until(&mut app, |app| {
+            app.world().resource::<AssetServer>().is_loaded(&file)
+        }));
+
+        // Layout reads the loaded asset instead of the source.
+        std::fs::remove_file(dir.path().join("data.txt")).unwrap();
+        let entity = app
+            .world_mut()
+            .spawn((
+                DynamicVelystFunc::new(source, "data")
+                    .with_positional("data.txt"),
+                WorldScene::default(),
+                Visibility::default(),
+            ))
+            .id();
+
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "cached"
+        }));
     }
 }
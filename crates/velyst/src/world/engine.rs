@@ -0,0 +1,599 @@
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use ecow::{EcoString, eco_format};
+use typst::diag::{FileError, FileResult, PackageError};
+use typst::foundations::{Array, Dict, IntoValue, Repr, Value};
+use typst::layout::Abs;
+use typst::syntax::package::{PackageSpec, PackageVersion};
+use typst::syntax::{
+    FileId, RootedPath, Source, SyntaxNode, VirtualPath, VirtualRoot,
+    ast,
+};
+
+/// The namespace of the virtual engine package.
+pub const ENGINE_NAMESPACE: &str = "velyst";
+/// The name of the virtual engine package.
+pub const ENGINE_NAME: &str = "engine";
+/// The version of the virtual engine package.
+pub const ENGINE_VERSION: PackageVersion = PackageVersion {
+    major: 0,
+    minor: 1,
+    patch: 0,
+};
+
+/// Engine state exposed to Typst through the virtual
+/// `@velyst/engine:0.1.0` package, which is served from memory.
+///
+/// ```typ
+/// #import "@velyst/engine:0.1.0": window, time, locale, input
+///
+/// #let columns = if window.width > 800pt { 2 } else { 1 }
+/// #let jumping = "jump" in input.pressed
+/// #let blink() = {
+///   import "@velyst/engine:0.1.0": time
+///   calc.rem(time.elapsed, 1.0) < 0.5
+/// }
+/// ```
+///
+/// Modules are only re-evaluated when an [`EngineItem`] they read
+/// changes, except for [`EngineItem::Time`], which changes every
+/// frame. Time is meant to be read at layout instead, by importing
+/// the package inside a function body: contents that read the
+/// package during layout are laid out again whenever an item
+/// imported by their source changes.
+///
+/// Window size, time and pressed actions are updated every frame,
+/// while [`Self::locale`] is left to the app.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct VelystEngineState {
+    /// Logical size and scale factor of the primary window.
+    pub window: EngineWindow,
+    /// Time since startup and since the last frame.
+    pub time: EngineTime,
+    /// The current locale as a BCP 47 language tag, e.g. `en-US`.
+    /// Defaults to the `LANG` environment variable, or `en`.
+    pub locale: EcoString,
+    /// Actions from [`VelystInputActions`] that are currently
+    /// pressed.
+    pub pressed: Vec<EcoString>,
+}
+
+impl Default for VelystEngineState {
+    fn default() -> Self {
+        Self {
+            window: EngineWindow::default(),
+            time: EngineTime::default(),
+            locale: env_locale().unwrap_or_else(|| "en".into()),
+            pressed: Vec::new(),
+        }
+    }
+}
+
+/// Logical size and scale factor of the primary window, exposed as
+/// `window`. Sizes are in points, matching Velyst's layout units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EngineWindow {
+    pub width: f32,
+    pub height: f32,
+    pub scale_factor: f32,
+}
+
+impl Default for EngineWindow {
+    fn default() -> Self {
+        Self {
+            width: 0.0,
+            height: 0.0,
+            scale_factor: 1.0,
+        }
+    }
+}
+
+/// Elapsed and delta time in seconds, exposed as `time`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EngineTime {
+    pub elapsed: f64,
+    pub delta: f64,
+}
+
+/// An item exported by the virtual engine package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EngineItem {
+    Window,
+    Time,
+    Locale,
+    Input,
+}
+
+impl EngineItem {
+    pub const ALL: [Self; 4] =
+        [Self::Window, Self::Time, Self::Locale, Self::Input];
+
+    /// The name of the item in Typst.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Window => "window",
+            Self::Time => "time",
+            Self::Locale => "locale",
+            Self::Input => "input",
+        }
+    }
+
+    /// Parse an item from its name in Typst.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|item| item.name() == name)
+    }
+}
+
+/// Sent whenever an [`EngineItem`] of the [`VelystEngineState`]
+/// changes.
+#[derive(Message, Debug, Clone, Copy)]
+pub struct VelystEngineUpdated {
+    pub item: EngineItem,
+}
+
+/// Named input actions exposed through `input.pressed`, each bound to
+/// any number of keys and mouse buttons.
+///
+/// Leave this empty to fill [`VelystEngineState::pressed`] from
+/// elsewhere, e.g. from an input manager.
+#[derive(Resource, Default, Debug, Clone, Deref, DerefMut)]
+pub struct VelystInputActions(
+    pub HashMap<EcoString, Vec<ActionBinding>>,
+);
+
+impl VelystInputActions {
+    /// Bind an action to the given inputs.
+    pub fn with(
+        mut self,
+        action: impl Into<EcoString>,
+        bindings: impl IntoIterator<Item = impl Into<ActionBinding>>,
+    ) -> Self {
+        self.0.insert(
+            action.into(),
+            bindings.into_iter().map(Into::into).collect(),
+        );
+        self
+    }
+}
+
+/// An input that triggers an action in [`VelystInputActions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActionBinding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl From<KeyCode> for ActionBinding {
+    fn from(key: KeyCode) -> Self {
+        Self::Key(key)
+    }
+}
+
+impl From<MouseButton> for ActionBinding {
+    fn from(button: MouseButton) -> Self {
+        Self::Mouse(button)
+    }
+}
+
+impl VelystEngineState {
+    /// The value of an item in Typst.
+    pub fn value(&self, item: EngineItem) -> Value {
+        match item {
+            EngineItem::Window => {
+                let mut dict = Dict::new();
+                dict.insert(
+                    "width".into(),
+                    Abs::pt(self.window.width as f64).into_value(),
+                );
+                dict.insert(
+                    "height".into(),
+                    Abs::pt(self.window.height as f64).into_value(),
+                );
+                dict.insert(
+                    "scale-factor".into(),
+                    (self.window.scale_factor as f64).into_value(),
+                );
+                dict.into_value()
+            }
+            EngineItem::Time => {
+                let mut dict = Dict::new();
+                dict.insert(
+                    "elapsed".into(),
+                    self.time.elapsed.into_value(),
+                );
+                dict.insert(
+                    "delta".into(),
+                    self.time.delta.into_value(),
+                );
+                dict.into_value()
+            }
+            EngineItem::Locale => self.locale.as_str().into_value(),
+            EngineItem::Input => {
+                let mut dict = Dict::new();
+                dict.insert(
+                    "pressed".into(),
+                    self.pressed
+                        .iter()
+                        .map(|action| action.as_str().into_value())
+                        .collect::<Array>()
+                        .into_value(),
+                );
+                dict.into_value()
+            }
+        }
+    }
+
+    /// Items whose values differ from another state.
+    pub fn changed_items(&self, other: &Self) -> Vec<EngineItem> {
+        EngineItem::ALL
+            .into_iter()
+            .filter(|item| match item {
+                EngineItem::Window => self.window != other.window,
+                EngineItem::Time => self.time != other.time,
+                EngineItem::Locale => self.locale != other.locale,
+                EngineItem::Input => self.pressed != other.pressed,
+            })
+            .collect()
+    }
+
+    /// The entrypoint of the engine package.
+    fn source_text(&self) -> String {
+        EngineItem::ALL
+            .into_iter()
+            .map(|item| {
+                format!(
+                    "#let {} = {}\n",
+                    item.name(),
+                    self.value(item).repr()
+                )
+            })
+            .collect()
+    }
+
+    /// Read a file of the engine package.
+    pub(super) fn read(&self, id: FileId) -> FileResult<Vec<u8>> {
+        match id.vpath().get_without_slash() {
+            "typst.toml" => Ok(format!(
+                "[package]\n\
+                 name = \"{ENGINE_NAME}\"\n\
+                 version = \"{ENGINE_VERSION}\"\n\
+                 entrypoint = \"lib.typ\"\n"
+            )
+            .into_bytes()),
+            "lib.typ" => Ok(self.source_text().into_bytes()),
+            path => Err(FileError::NotFound(path.into())),
+        }
+    }
+}
+
+/// The entrypoint of the engine package.
+pub fn engine_entrypoint() -> FileId {
+    let spec = PackageSpec {
+        namespace: ENGINE_NAMESPACE.into(),
+        name: ENGINE_NAME.into(),
+        version: ENGINE_VERSION,
+    };
+    FileId::new(RootedPath::new(
+        VirtualRoot::Package(spec),
+        VirtualPath::new("/lib.typ").unwrap(),
+    ))
+}
+
+/// Whether a package is the virtual engine package. Other versions
+/// of it are reported as not found.
+pub(super) fn check_engine_package(
+    spec: &PackageSpec,
+) -> Option<FileResult<()>> {
+    if spec.namespace != ENGINE_NAMESPACE || spec.name != ENGINE_NAME
+    {
+        return None;
+    }
+
+    if spec.version != ENGINE_VERSION {
+        return Some(Err(FileError::Package(PackageError::Other(
+            Some(eco_format!(
+                "{spec} does not exist, use \
+                 @{ENGINE_NAMESPACE}/{ENGINE_NAME}:{ENGINE_VERSION}"
+            )),
+        ))));
+    }
+
+    Some(Ok(()))
+}
+
+/// Engine items read by a source file, directly from the engine
+/// package.
+///
+/// Items listed in an import are read. Bare and wildcard imports
+/// read the items whose names appear as identifiers or fields in
+/// the source, e.g. `engine.window` or `window`.
+pub fn imported_engine_items(source: &Source) -> HashSet<EngineItem> {
+    let mut items = HashSet::new();
+    let mut open = false;
+    collect_engine_items(source.root(), &mut items, &mut open);
+    if open {
+        collect_engine_names(source.root(), &mut items);
+    }
+    items
+}
+
+/// Collect the items listed in engine package imports. `open` is
+/// set if any import is bare or a wildcard.
+fn collect_engine_items(
+    node: &SyntaxNode,
+    items: &mut HashSet<EngineItem>,
+    open: &mut bool,
+) {
+    if let Some(import) = node.cast::<ast::ModuleImport>()
+        && let ast::Expr::Str(path) = import.source()
+        && path
+            .get()
+            .parse::<PackageSpec>()
+            .is_ok_and(|spec| check_engine_package(&spec).is_some())
+    {
+        match import.imports() {
+            Some(ast::Imports::Items(imports)) => {
+                items.extend(imports.iter().filter_map(|item| {
+                    item.path()
+                        .iter()
+                        .next()
+                        .and_then(|name| EngineItem::from_name(&name))
+                }));
+            }
+            _ => *open = true,
+        }
+    }
+
+    for child in node.children() {
+        collect_engine_items(child, items, open);
+    }
+}
+
+/// Collect the items named by any identifier in the tree.
+fn collect_engine_names(
+    node: &SyntaxNode,
+    items: &mut HashSet<EngineItem>,
+) {
+    if let Some(ident) = node.cast::<ast::Ident>() {
+        items.extend(EngineItem::from_name(&ident));
+    }
+
+    for child in node.children() {
+        collect_engine_names(child, items);
+    }
+}
+
+/// Update [`VelystEngineState`] from the primary window, time and
+/// input.
+pub(super) fn update_engine_state(
+    mut state: ResMut<VelystEngineState>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    time: Res<Time>,
+    actions: Res<VelystInputActions>,
+    keys: Option<Res<ButtonInput<KeyCode>>>,
+    mouse: Option<Res<ButtonInput<MouseButton>>>,
+) {
+    if let Ok(window) = q_window.single() {
+        let window = EngineWindow {
+            width: window.width(),
+            height: window.height(),
+            scale_factor: window.scale_factor(),
+        };
+        if state.window != window {
+            state.window = window;
+        }
+    }
+
+    let time = EngineTime {
+        elapsed: time.elapsed_secs_f64(),
+        delta: time.delta_secs_f64(),
+    };
+    if state.time != time {
+        state.time = time;
+    }
+
+    if actions.is_empty() {
+        return;
+    }
+
+    let is_pressed = |binding: &ActionBinding| match binding {
+        ActionBinding::Key(key) => {
+            keys.as_ref().is_some_and(|keys| keys.pressed(*key))
+        }
+        ActionBinding::Mouse(button) => {
+            mouse.as_ref().is_some_and(|mouse| mouse.pressed(*button))
+        }
+    };
+    let mut pressed = actions
+        .iter()
+        .filter(|(_, bindings)| bindings.iter().any(is_pressed))
+        .map(|(action, _)| action.clone())
+        .collect::<Vec<_>>();
+    pressed.sort();
+
+    if state.pressed != pressed {
+        state.pressed = pressed;
+    }
+}
+
+/// Send a [`VelystEngineUpdated`] for every item that changed since
+/// the last run.
+pub(super) fn detect_engine_changes(
+    state: Res<VelystEngineState>,
+    mut prev: Local<Option<VelystEngineState>>,
+    mut engine_updates: MessageWriter<VelystEngineUpdated>,
+) {
+    if !state.is_changed() {
+        return;
+    }
+
+    if let Some(prev) = prev.as_ref() {
+        engine_updates.write_batch(
+            state
+                .changed_items(prev)
+                .into_iter()
+                .map(|item| VelystEngineUpdated { item }),
+        );
+    }
+
+    *prev = Some(state.clone());
+}
+
+/// The locale from the environment as a BCP 47 language tag.
+fn env_locale() -> Option<EcoString> {
+    let lang = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|lang| !lang.is_empty())?;
+
+    // e.g. `en_US.UTF-8` into `en-US`.
+    let tag = lang.split(['.', '@']).next()?.replace('_', "-");
+    match tag.as_str() {
+        "" | "C" | "POSIX" => None,
+        _ => Some(tag.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::{VelystModuleUpdated, VelystSource};
+    use crate::func::DynamicVelystFunc;
+    use crate::renderer::{VelystFrame, WorldScene};
+    use crate::test_utils::{
+        frame_text, test_app, update_until, write_file,
+    };
+
+    fn items(text: &str) -> HashSet<EngineItem> {
+        imported_engine_items(&Source::detached(text))
+    }
+
+    #[test]
+    fn open_imports_read_named_items() {
+        assert_eq!(
+            items(
+                "#import \"@velyst/engine:0.1.0\": window, locale\n\
+                 #window"
+            ),
+            HashSet::from([EngineItem::Window, EngineItem::Locale])
+        );
+        assert_eq!(
+            items(
+                "#import \"@velyst/engine:0.1.0\" as e\n\
+                 #e.window.width"
+            ),
+            HashSet::from([EngineItem::Window])
+        );
+        assert_eq!(
+            items("#import \"@velyst/engine:0.1.0\": *\n#input"),
+            HashSet::from([EngineItem::Input])
+        );
+        assert!(items("#import \"@velyst/engine:0.1.0\"").is_empty());
+    }
+
+    #[derive(Resource, Default)]
+    struct Evaluations(usize);
+
+    #[test]
+    fn time_only_relayouts() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "main.typ",
+            "#import \"@velyst/engine:0.1.0\": time\n\
+             #let start = time.elapsed\n\
+             #let clock() = {\n\
+             \x20 import \"@velyst/engine:0.1.0\": time\n\
+             \x20 str(time.elapsed)\n\
+             }",
+        );
+
+        let mut app = test_app(dir.path());
+        app.init_resource::<Evaluations>().add_systems(
+            Update,
+            |mut updates: MessageReader<VelystModuleUpdated>,
+             mut evaluations: ResMut<Evaluations>| {
+                evaluations.0 += updates.read().count();
+            },
+        );
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let entity = app
+            .world_mut()
+            .spawn((
+                DynamicVelystFunc::new(handle, "clock"),
+                WorldScene::default(),
+                Visibility::default(),
+            ))
+            .id();
+
+        assert!(update_until(&mut app, |app| {
+            !frame_text(app, entity).is_empty()
+        }));
+        let first = frame_text(&app, entity);
+
+        // The function body reads the new time at layout, without
+        // evaluating the module again.
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) != first
+        }));
+        assert_eq!(app.world().resource::<Evaluations>().0, 1);
+    }
+
+    #[test]
+    fn layout_reads_only_imported_items() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "main.typ",
+            "#let width() = {\n\
+             \x20 import \"@velyst/engine:0.1.0\": window\n\
+             \x20 str(window.width.pt())\n\
+             }",
+        );
+
+        let mut app = test_app(dir.path());
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystSource>("main.typ");
+        let entity = app
+            .world_mut()
+            .spawn((
+                DynamicVelystFunc::new(handle, "width"),
+                WorldScene::default(),
+                Visibility::default(),
+            ))
+            .id();
+
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "0"
+        }));
+        let laid_out = |app: &App| {
+            app.world()
+                .entity(entity)
+                .get_ref::<VelystFrame>()
+                .unwrap()
+                .last_changed()
+        };
+        let first = laid_out(&app);
+
+        // Time advances every update, but isn't read.
+        for _ in 0..10 {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            app.update();
+        }
+        assert_eq!(laid_out(&app), first);
+
+        app.world_mut()
+            .resource_mut::<VelystEngineState>()
+            .window
+            .width = 80.0;
+        assert!(update_until(&mut app, |app| {
+            frame_text(app, entity) == "80"
+        }));
+    }
+}
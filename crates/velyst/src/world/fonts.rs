@@ -2,12 +2,121 @@ use std::fs;
 use std::path::PathBuf;
 use std::sync::OnceLock;
 
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::platform::collections::{HashMap, HashSet};
 use bevy::prelude::*;
+use bevy::tasks::futures::check_ready;
+use bevy::tasks::{IoTaskPool, Task};
 use bevy_vello::vello_svg::usvg::fontdb::{Database, Source};
+use ecow::{EcoString, eco_format};
+use typst::Library;
 use typst::foundations::Bytes;
-use typst::text::{Font, FontBook, FontInfo};
+use typst::layout::{Frame, FrameItem};
+use typst::text::{
+    Font, FontBook, FontFamily, FontInfo, FontList, TextElem,
+};
 use typst::utils::LazyHash;
 
+use super::TypstLibrary;
+use crate::diag::{DiagnosticStage, VelystDiagnostic};
+use crate::func::VelystContent;
+use crate::renderer::VelystFrame;
+
+/// A place [`TypstFonts`] can take fonts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FontSource {
+    /// Fonts embedded in the binary with the `embed-fonts` feature.
+    Embedded,
+    /// Fonts loaded as [`VelystFont`] assets or added with
+    /// [`TypstFonts::add_font`].
+    Assets,
+    /// Fonts in [`VelystFontConfig::font_dirs`].
+    Dirs,
+    /// Fonts installed on the system.
+    System,
+}
+
+/// Configures which fonts are available to Typst.
+///
+/// Changing this resource rebuilds [`TypstFonts`].
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub struct VelystFontConfig {
+    /// Sources to take fonts from, in priority order. Sources that
+    /// are not listed are not used.
+    pub sources: Vec<FontSource>,
+    /// Directories searched for [`FontSource::Dirs`].
+    pub font_dirs: Vec<PathBuf>,
+    /// Default font families, in fallback order. Typst's default
+    /// families are used if empty.
+    ///
+    /// When set, a warning is emitted whenever laid out text falls
+    /// back to a family outside of this list.
+    pub families: Vec<EcoString>,
+}
+
+impl Default for VelystFontConfig {
+    fn default() -> Self {
+        Self::system()
+    }
+}
+
+impl VelystFontConfig {
+    /// Use font dirs and system fonts, followed by embedded and asset
+    /// fonts.
+    pub fn system() -> Self {
+        Self {
+            sources: vec![
+                FontSource::Dirs,
+                FontSource::System,
+                FontSource::Embedded,
+                FontSource::Assets,
+            ],
+            font_dirs: Vec::new(),
+            families: Vec::new(),
+        }
+    }
+
+    /// Only use fonts embedded in the binary.
+    pub fn embedded_only() -> Self {
+        Self {
+            sources: vec![FontSource::Embedded],
+            ..Self::system()
+        }
+    }
+
+    /// Only use fonts loaded as assets.
+    pub fn assets_only() -> Self {
+        Self {
+            sources: vec![FontSource::Assets],
+            ..Self::system()
+        }
+    }
+
+    pub fn with_font_dirs(
+        mut self,
+        font_dirs: impl IntoIterator<Item = impl Into<PathBuf>>,
+    ) -> Self {
+        self.font_dirs =
+            font_dirs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_families(
+        mut self,
+        families: impl IntoIterator<Item = impl Into<EcoString>>,
+    ) -> Self {
+        self.families =
+            families.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Whether the given family is part of [`Self::families`].
+    pub fn contains_family(&self, family: &str) -> bool {
+        self.families.iter().any(|f| f.eq_ignore_ascii_case(family))
+    }
+}
+
 /// Searches for fonts.
 #[derive(Resource, Debug, Clone)]
 pub struct TypstFonts {
@@ -15,17 +124,20 @@ pub struct TypstFonts {
     pub book: LazyHash<FontBook>,
     /// Slots that the fonts are loaded into.
     pub fonts: Vec<FontSlot>,
+    /// Sources in priority order.
+    sources: Vec<FontSource>,
+    /// Fonts found in each source.
+    faces: HashMap<FontSource, Vec<(FontInfo, FontSlot)>>,
+    /// Fonts added with [`Self::add_font`], kept across rescans.
+    added_faces: Vec<(FontInfo, FontSlot)>,
+    /// Fonts of the loaded [`VelystFont`] assets, listed after the
+    /// added fonts.
+    asset_faces: Vec<(FontInfo, FontSlot)>,
 }
 
 impl Default for TypstFonts {
     fn default() -> Self {
-        let mut fonts = Self {
-            book: LazyHash::new(FontBook::new()),
-            fonts: Vec::new(),
-        };
-
-        fonts.search(&[]);
-        fonts
+        Self::from_config(&VelystFontConfig::default())
     }
 }
 
@@ -55,60 +167,595 @@ impl FontSlot {
 }
 
 impl TypstFonts {
-    /// Search everything that is available.
-    pub fn search(&mut self, font_paths: &[PathBuf]) {
-        let mut db = Database::new();
+    /// Search all fonts available with the given config, blocking
+    /// until every source is scanned.
+    pub fn from_config(config: &VelystFontConfig) -> Self {
+        let mut fonts = Self::immediate(config);
+        for source in fonts.pending_sources() {
+            fonts.set_faces(source, scan_source(source, config));
+        }
+        fonts
+    }
+
+    /// Fonts of the given config that are available without scanning
+    /// the file system, i.e. embedded fonts.
+    ///
+    /// The remaining sources are listed by
+    /// [`Self::pending_sources`].
+    pub fn immediate(config: &VelystFontConfig) -> Self {
+        let mut fonts = Self {
+            book: LazyHash::new(FontBook::new()),
+            fonts: Vec::new(),
+            sources: config.sources.clone(),
+            faces: HashMap::new(),
+            added_faces: Vec::new(),
+            asset_faces: Vec::new(),
+        };
 
-        // Font paths have highest priority.
-        for path in font_paths {
-            db.load_fonts_dir(path);
+        if config.sources.contains(&FontSource::Embedded) {
+            fonts
+                .faces
+                .insert(FontSource::Embedded, embedded_faces());
         }
 
-        // System fonts have second priority.
-        db.load_system_fonts();
-
-        for face in db.faces() {
-            let path = match &face.source {
-                Source::File(path) | Source::SharedFile(path, _) => {
-                    path
-                }
-                // We never add binary sources to the database, so
-                // there shouln't be any.
-                Source::Binary(_) => continue,
+        fonts.rebuild_book();
+        fonts
+    }
+
+    /// Configured sources that have not been scanned yet.
+    pub fn pending_sources(&self) -> Vec<FontSource> {
+        self.sources
+            .iter()
+            .filter(|source| {
+                matches!(
+                    source,
+                    FontSource::Dirs | FontSource::System
+                ) && !self.faces.contains_key(*source)
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Replace the fonts of a source.
+    fn set_faces(
+        &mut self,
+        source: FontSource,
+        faces: Vec<(FontInfo, FontSlot)>,
+    ) {
+        self.faces.insert(source, faces);
+        self.rebuild_book();
+    }
+
+    /// Add every font face in the given data as a
+    /// [`FontSource::Assets`] font, returning the number of faces
+    /// added.
+    ///
+    /// The faces are only available if [`FontSource::Assets`] is one
+    /// of the configured sources.
+    pub fn add_font(&mut self, data: Bytes) -> usize {
+        let faces = font_faces(data).collect::<Vec<_>>();
+        let count = faces.len();
+
+        self.added_faces.extend(faces);
+        if self.sources.contains(&FontSource::Assets) {
+            self.rebuild_book();
+        }
+
+        count
+    }
+
+    /// Replace the fonts of the loaded [`VelystFont`] assets.
+    fn set_asset_fonts(
+        &mut self,
+        fonts: impl Iterator<Item = Bytes>,
+    ) {
+        self.asset_faces = fonts.flat_map(font_faces).collect();
+        if self.sources.contains(&FontSource::Assets) {
+            self.rebuild_book();
+        }
+    }
+
+    /// Combine the fonts of all sources in priority order.
+    fn rebuild_book(&mut self) {
+        let mut book = FontBook::new();
+        self.fonts.clear();
+
+        for source in self.sources.iter() {
+            let (added_faces, asset_faces) = match source {
+                FontSource::Assets => (
+                    self.added_faces.as_slice(),
+                    self.asset_faces.as_slice(),
+                ),
+                _ => (&[][..], &[][..]),
             };
+            for (info, slot) in self
+                .faces
+                .get(source)
+                .into_iter()
+                .flatten()
+                .chain(added_faces)
+                .chain(asset_faces)
+            {
+                book.push(info.clone());
+                self.fonts.push(slot.clone());
+            }
+        }
+
+        self.book = LazyHash::new(book);
+    }
+}
 
-            let info = db
-                .with_face_data(face.id, FontInfo::new)
-                .expect("database must contain this font");
+/// Scan the file system for the fonts of a source.
+fn scan_source(
+    source: FontSource,
+    config: &VelystFontConfig,
+) -> Vec<(FontInfo, FontSlot)> {
+    match source {
+        FontSource::Dirs => search_faces(|db| {
+            for dir in config.font_dirs.iter() {
+                db.load_fonts_dir(dir);
+            }
+        }),
+        FontSource::System => {
+            search_faces(Database::load_system_fonts)
+        }
+        FontSource::Embedded | FontSource::Assets => Vec::new(),
+    }
+}
+
+/// Collect the font faces of a database filled by `load`.
+fn search_faces(
+    load: impl FnOnce(&mut Database),
+) -> Vec<(FontInfo, FontSlot)> {
+    let mut db = Database::new();
+    load(&mut db);
 
-            if let Some(info) = info {
-                self.book.push(info);
-                self.fonts.push(FontSlot {
+    let mut faces = Vec::new();
+    for face in db.faces() {
+        let path = match &face.source {
+            Source::File(path) | Source::SharedFile(path, _) => path,
+            // We never add binary sources to the database, so
+            // there shouln't be any.
+            Source::Binary(_) => continue,
+        };
+
+        let info = db
+            .with_face_data(face.id, FontInfo::new)
+            .expect("database must contain this font");
+
+        if let Some(info) = info {
+            faces.push((
+                info,
+                FontSlot {
                     path: path.clone(),
                     index: face.index,
                     font: OnceLock::new(),
-                });
-            }
+                },
+            ));
         }
-
-        // Embedded fonts have lowest priority.
-        #[cfg(feature = "embed-fonts")]
-        self.add_embedded();
     }
 
-    /// Add fonts that are embedded in the binary.
+    faces
+}
+
+/// The font faces in the given data, already loaded.
+fn font_faces(
+    data: Bytes,
+) -> impl Iterator<Item = (FontInfo, FontSlot)> {
+    Font::iter(data).map(|font| {
+        (
+            font.info().clone(),
+            FontSlot {
+                path: PathBuf::new(),
+                index: font.index(),
+                font: OnceLock::from(Some(font)),
+            },
+        )
+    })
+}
+
+/// Fonts that are embedded in the binary.
+fn embedded_faces() -> Vec<(FontInfo, FontSlot)> {
     #[cfg(feature = "embed-fonts")]
-    fn add_embedded(&mut self) {
-        for data in typst_assets::fonts() {
-            let buffer = Bytes::new(data);
-            for (i, font) in Font::iter(buffer).enumerate() {
-                self.book.push(font.info().clone());
-                self.fonts.push(FontSlot {
-                    path: PathBuf::new(),
-                    index: i as u32,
-                    font: OnceLock::from(Some(font)),
+    {
+        typst_assets::fonts()
+            .flat_map(|data| font_faces(Bytes::new(data)))
+            .collect()
+    }
+
+    #[cfg(not(feature = "embed-fonts"))]
+    Vec::new()
+}
+
+/// A font file loaded as a Bevy asset.
+///
+/// Every loaded [`VelystFont`] is registered in [`TypstFonts`], and
+/// replaced or removed along with the asset.
+#[derive(Asset, TypePath, Deref, Clone)]
+pub struct VelystFont(pub Bytes);
+
+#[derive(Default, TypePath)]
+pub struct VelystFontLoader;
+
+impl AssetLoader for VelystFontLoader {
+    type Asset = VelystFont;
+
+    type Settings = ();
+
+    type Error = std::io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let data = Bytes::new(bytes);
+        if Font::iter(data.clone()).next().is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "no font faces found",
+            ));
+        }
+
+        Ok(VelystFont(data))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ttf", "otf", "ttc"]
+    }
+}
+
+/// Font sources being scanned in the background.
+#[derive(Resource, Default)]
+pub struct VelystFontScan {
+    tasks: Vec<(FontSource, Task<Vec<(FontInfo, FontSlot)>>)>,
+    total: usize,
+}
+
+impl VelystFontScan {
+    /// Whether every source has been scanned.
+    pub fn is_finished(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
+
+/// Sent whenever a background scan of a [`FontSource`] completes.
+#[derive(Message, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VelystFontScanProgress {
+    /// The source that was scanned.
+    pub source: FontSource,
+    /// Number of font faces found in the source.
+    pub faces: usize,
+    /// Number of sources scanned so far.
+    pub completed: usize,
+    /// Number of sources to scan.
+    pub total: usize,
+}
+
+impl VelystFontScanProgress {
+    /// Whether this was the last source to scan.
+    pub fn is_finished(&self) -> bool {
+        self.completed == self.total
+    }
+}
+
+/// Scan the font sources of [`VelystFontConfig`] on the
+/// [`IoTaskPool`], restarting whenever the config changes, and add
+/// the fonts to [`TypstFonts`] as each scan completes.
+pub(super) fn scan_fonts(
+    config: Res<VelystFontConfig>,
+    mut fonts: ResMut<TypstFonts>,
+    mut scan: ResMut<VelystFontScan>,
+    font_assets: Res<Assets<VelystFont>>,
+    mut progress: MessageWriter<VelystFontScanProgress>,
+) {
+    if config.is_changed() {
+        // `TypstFonts` is built from the config present when the
+        // plugin is added, which may have been replaced since.
+        if !config.is_added() || fonts.sources != config.sources {
+            let added_faces = std::mem::take(&mut fonts.added_faces);
+            *fonts = TypstFonts::immediate(&config);
+            fonts.added_faces = added_faces;
+            fonts.set_asset_fonts(
+                font_assets.iter().map(|(_, font)| font.0.clone()),
+            );
+        }
+
+        let pool = IoTaskPool::get();
+        scan.tasks = fonts
+            .pending_sources()
+            .into_iter()
+            .map(|source| {
+                let config = config.clone();
+                let task = pool.spawn(async move {
+                    scan_source(source, &config)
                 });
+                (source, task)
+            })
+            .collect();
+        scan.total = scan.tasks.len();
+    }
+
+    let mut finished = Vec::new();
+    scan.tasks
+        .retain_mut(|(source, task)| match check_ready(task) {
+            Some(faces) => {
+                finished.push((*source, faces));
+                false
             }
+            None => true,
+        });
+
+    for (source, faces) in finished {
+        let count = faces.len();
+        fonts.set_faces(source, faces);
+        progress.write(VelystFontScanProgress {
+            source,
+            faces: count,
+            completed: scan.total - scan.tasks.len(),
+            total: scan.total,
+        });
+    }
+}
+
+/// Rebuild the [`VelystFont`] assets of [`TypstFonts`] whenever one
+/// is added, modified or removed.
+pub(super) fn register_font_assets(
+    mut evr_font_event: MessageReader<AssetEvent<VelystFont>>,
+    mut fonts: ResMut<TypstFonts>,
+    font_assets: Res<Assets<VelystFont>>,
+) {
+    let mut changed = false;
+    for font_event in evr_font_event.read() {
+        changed |= matches!(
+            font_event,
+            AssetEvent::Added { .. }
+                | AssetEvent::Modified { .. }
+                | AssetEvent::Removed { .. }
+        );
+    }
+    if changed {
+        fonts.set_asset_fonts(
+            font_assets.iter().map(|(_, font)| font.0.clone()),
+        );
+    }
+}
+
+/// Set the default font families of a library, if any are
+/// configured.
+pub fn set_font_families(
+    library: &mut Library,
+    config: &VelystFontConfig,
+) {
+    if config.families.is_empty() {
+        return;
+    }
+
+    library.styles.set(
+        TextElem::font,
+        FontList(
+            config
+                .families
+                .iter()
+                .map(|family| FontFamily::new(family))
+                .collect(),
+        ),
+    );
+}
+
+/// Relayout every [`VelystContent`] when [`TypstFonts`] or the
+/// [`TypstLibrary`] changes, so that new fonts are picked up.
+pub(super) fn invalidate_layout(
+    fonts: Res<TypstFonts>,
+    library: Res<TypstLibrary>,
+    mut q_contents: Query<&mut VelystContent>,
+) {
+    let fonts_changed = fonts.is_changed() && !fonts.is_added();
+    let library_changed = library.is_changed() && !library.is_added();
+    if !fonts_changed && !library_changed {
+        return;
+    }
+
+    for mut content in q_contents.iter_mut() {
+        content.set_changed();
+    }
+}
+
+/// Warn once per family when laid out text uses a font family outside
+/// of [`VelystFontConfig::families`].
+pub(super) fn warn_font_fallback(
+    config: Res<VelystFontConfig>,
+    q_frames: Query<(Entity, &VelystFrame), Changed<VelystFrame>>,
+    mut warned: Local<HashSet<EcoString>>,
+    mut diagnostics: MessageWriter<VelystDiagnostic>,
+) {
+    if config.is_changed() {
+        warned.clear();
+    }
+    if config.families.is_empty() {
+        return;
+    }
+
+    for (entity, frame) in q_frames.iter() {
+        let Some(frame) = &frame.0 else {
+            continue;
+        };
+
+        let mut families = HashSet::new();
+        collect_families(frame, &mut families);
+
+        for family in families {
+            if config.contains_family(&family)
+                || !warned.insert(family.clone())
+            {
+                continue;
+            }
+
+            diagnostics.write(
+                VelystDiagnostic::warning(
+                    DiagnosticStage::Layout,
+                    eco_format!(
+                        "text fell back to font family \"{family}\", \
+                         which is not part of the configured families"
+                    ),
+                )
+                .with_entity(entity),
+            );
+        }
+    }
+}
+
+/// Collect the font families used by the text in a frame.
+fn collect_families(
+    frame: &Frame,
+    families: &mut HashSet<EcoString>,
+) {
+    for (_, item) in frame.items() {
+        match item {
+            FrameItem::Group(group) => {
+                collect_families(&group.frame, families)
+            }
+            FrameItem::Text(text) => {
+                families
+                    .insert(text.font.info().family.as_str().into());
+            }
+            _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diag::VelystDiagnosticPlugin;
+    use crate::world::VelystWorldPlugin;
+
+    #[test]
+    fn config_inserted_after_plugin() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin::default(),
+            VelystDiagnosticPlugin,
+            VelystWorldPlugin,
+        ));
+        app.insert_resource(VelystFontConfig::embedded_only());
+        app.update();
+
+        let fonts = app.world().resource::<TypstFonts>();
+        assert_eq!(fonts.sources, [FontSource::Embedded]);
+        assert!(!fonts.faces.contains_key(&FontSource::System));
+    }
+
+    #[cfg(feature = "embed-fonts")]
+    #[test]
+    fn added_fonts_survive_config_changes() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin::default(),
+            VelystDiagnosticPlugin,
+            VelystWorldPlugin,
+        ));
+        // Inserted after the plugin, so the first scan rebuilds the
+        // fonts.
+        app.insert_resource(VelystFontConfig::assets_only());
+
+        let data = Bytes::new(typst_assets::fonts().next().unwrap());
+        let family =
+            Font::new(data.clone(), 0).unwrap().info().family.clone();
+        let resolves = |app: &App| {
+            let fonts = app.world().resource::<TypstFonts>();
+            fonts.book.select_family(&family.to_lowercase()).count()
+                > 0
+        };
+
+        app.world_mut().resource_mut::<TypstFonts>().add_font(data);
+        assert!(resolves(&app));
+        app.update();
+        assert!(resolves(&app));
+
+        app.world_mut()
+            .resource_mut::<VelystFontConfig>()
+            .set_changed();
+        app.update();
+        assert!(resolves(&app));
+    }
+    #[cfg(feature = "embed-fonts")]
+    #[test]
+    fn reload_font_asset() {
+        use crate::test_utils::update_until;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = App::new();
+        app.insert_resource(VelystFontConfig::assets_only());
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                file_path: dir.path().to_string_lossy().into(),
+                ..default()
+            },
+            VelystDiagnosticPlugin,
+            VelystWorldPlugin,
+        ));
+
+        let families = |app: &App| {
+            let fonts = app.world().resource::<TypstFonts>();
+            fonts
+                .book
+                .families()
+                .map(|(family, _)| family.to_string())
+                .collect::<Vec<_>>()
+        };
+        let family_of = |data: &'static [u8]| {
+            Font::new(Bytes::new(data), 0)
+                .unwrap()
+                .info()
+                .family
+                .clone()
+        };
+
+        let mut data = typst_assets::fonts();
+        let first = data.next().unwrap();
+        let second = data
+            .find(|data| family_of(data) != family_of(first))
+            .unwrap();
+        let faces = |data: &'static [u8]| {
+            Font::iter(Bytes::new(data)).count()
+        };
+
+        let path = dir.path().join("font.otf");
+        fs::write(&path, first).unwrap();
+        let handle = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<VelystFont>("font.otf");
+        assert!(update_until(&mut app, |app| {
+            families(app) == [family_of(first)]
+        }));
+        assert_eq!(
+            app.world().resource::<TypstFonts>().fonts.len(),
+            faces(first)
+        );
+
+        // The reloaded font replaces the previous one.
+        fs::write(&path, second).unwrap();
+        app.world().resource::<AssetServer>().reload("font.otf");
+        assert!(update_until(&mut app, |app| {
+            families(app) == [family_of(second)]
+        }));
+        assert_eq!(
+            app.world().resource::<TypstFonts>().fonts.len(),
+            faces(second)
+        );
+
+        drop(handle);
+        assert!(update_until(&mut app, |app| {
+            app.world().resource::<TypstFonts>().fonts.is_empty()
+        }));
+    }
+}
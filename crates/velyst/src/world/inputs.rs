@@ -0,0 +1,84 @@
+use bevy::prelude::*;
+use typst::Library;
+use typst::foundations::{Dict, IntoValue, Str};
+use typst::utils::LazyHash;
+
+use super::LibrarySettings;
+
+/// Data exposed to every Typst module as `sys.inputs`, e.g. settings
+/// such as difficulty, platform, accessibility flags or the build
+/// version.
+///
+/// As a resource, the inputs are visible to every module. As a
+/// component on a [`VelystFunc`][crate::func::VelystFunc] entity,
+/// they are merged over the global inputs for that entity only,
+/// overriding keys that exist in both.
+///
+/// Changing the inputs re-evaluates the affected modules and
+/// recompiles the entities that use them.
+///
+/// # Example
+///
+/// ```
+/// use velyst::world::VelystInputs;
+///
+/// let inputs = VelystInputs::default()
+///     .with("difficulty", "hard")
+///     .with("high-contrast", true);
+/// ```
+///
+/// ```typ
+/// #let difficulty = sys.inputs.at("difficulty", default: "normal")
+/// ```
+#[derive(
+    Resource, Component, Default, Debug, Clone, Deref, DerefMut,
+)]
+#[require(InputsLibrary)]
+pub struct VelystInputs(pub Dict);
+
+impl VelystInputs {
+    /// Insert an input, replacing any previous value for the key.
+    pub fn with(
+        mut self,
+        key: impl Into<Str>,
+        value: impl IntoValue,
+    ) -> Self {
+        self.0.insert(key.into(), value.into_value());
+        self
+    }
+}
+
+/// The library an entity with [`VelystInputs`] is compiled with.
+///
+/// Set to `None` once the inputs are removed, so that the entity is
+/// recompiled with the global library again.
+#[derive(Component, Default)]
+pub(crate) struct InputsLibrary(pub Option<LazyHash<Library>>);
+
+/// Rebuild the [`InputsLibrary`] of entities whose [`VelystInputs`]
+/// changed, or of every such entity when the global configuration
+/// changed.
+pub(super) fn update_entity_libraries(
+    settings: LibrarySettings,
+    mut q_inputs: Query<(Ref<VelystInputs>, &mut InputsLibrary)>,
+    mut q_libraries: Query<&mut InputsLibrary, Without<VelystInputs>>,
+    mut removed_inputs: RemovedComponents<VelystInputs>,
+) {
+    let global_changed = settings.is_changed();
+
+    for (entity_inputs, mut library) in q_inputs.iter_mut() {
+        if !global_changed && !entity_inputs.is_changed() {
+            continue;
+        }
+
+        let merged =
+            settings.inputs.0.clone() + entity_inputs.0.clone();
+        library.0 = Some(LazyHash::new(settings.build(&merged)));
+    }
+
+    for entity in removed_inputs.read() {
+        if let Ok(mut library) = q_libraries.get_mut(entity) {
+            library.0 = None;
+        }
+    }
+}
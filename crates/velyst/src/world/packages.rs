@@ -0,0 +1,741 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::asset::io::file::FileAssetReader;
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use ecow::{EcoString, eco_format};
+use typst::diag::{FileError, FileResult, PackageError};
+use typst::syntax::package::PackageSpec;
+
+/// Directories searched in order for packages, each laid out as
+/// `{namespace}/{name}/{version}` like the Typst CLI expects.
+///
+/// Defaults to:
+/// 1. `assets/typst_packages`, for packages vendored with the
+///    project.
+/// 2. `{data-dir}/typst/packages`, for `@local` packages.
+/// 3. `{cache-dir}/typst/packages`, for packages downloaded by the
+///    Typst CLI or by [`TypstPackageDownload`].
+///
+/// Packages under [`TypstPackageDownload::cache_dir`] are only used
+/// if they pass its [`PackageLock`], even when it is one of the roots.
+#[derive(Resource, Debug, Clone, Deref, DerefMut)]
+pub struct TypstPackageRoots(pub Vec<PathBuf>);
+
+impl Default for TypstPackageRoots {
+    fn default() -> Self {
+        let base = FileAssetReader::get_base_path();
+        let mut roots = vec![
+            base.join(AssetPlugin::default().file_path)
+                .join("typst_packages"),
+        ];
+        roots.extend(
+            [dirs::data_dir(), dirs::cache_dir()]
+                .into_iter()
+                .flatten()
+                .map(|dir| dir.join("typst").join("packages")),
+        );
+
+        Self(roots)
+    }
+}
+
+impl TypstPackageRoots {
+    /// Find the first root that contains the given package.
+    pub fn find(&self, spec: &PackageSpec) -> Option<PathBuf> {
+        self.iter()
+            .map(|root| package_subdir(root, spec))
+            .find(|dir| dir.is_dir())
+    }
+}
+
+/// Controls whether packages missing from [`TypstPackageRoots`] can
+/// be fetched, where they are cached locally and how they are
+/// verified.
+///
+/// Enabled by default in debug builds, disabled in release builds.
+/// Change this resource at runtime to override the default behavior.
+#[derive(Resource)]
+pub struct TypstPackageDownload {
+    pub enabled: bool,
+    /// Local directory where fetched packages are cached.
+    /// Defaults to `{cache-dir}/typst/packages`, shared with the
+    /// Typst CLI.
+    pub cache_dir: PathBuf,
+    /// Source of package archives. Defaults to [`RegistryFetcher`]
+    /// with the `download` feature, and to `None` without it.
+    pub fetcher: Option<Arc<dyn PackageFetcher>>,
+    /// Expected hashes of package archives.
+    pub lock: PackageLock,
+}
+
+impl Default for TypstPackageDownload {
+    fn default() -> Self {
+        let cache_dir = dirs::cache_dir()
+            .map(|dir| dir.join("typst").join("packages"))
+            .unwrap_or_else(|| {
+                FileAssetReader::get_base_path()
+                    .join(AssetPlugin::default().file_path)
+                    .join("typst_packages")
+            });
+
+        #[cfg(feature = "download")]
+        let fetcher = Some(Arc::new(RegistryFetcher::default())
+            as Arc<dyn PackageFetcher>);
+        #[cfg(not(feature = "download"))]
+        let fetcher = None;
+
+        Self {
+            enabled: cfg!(debug_assertions),
+            cache_dir,
+            fetcher,
+            lock: PackageLock::default(),
+        }
+    }
+}
+
+/// Fetches the gzipped tarball of a package, e.g. from the official
+/// registry, a local mirror or an in-memory test double.
+pub trait PackageFetcher: Send + Sync + 'static {
+    fn fetch(
+        &self,
+        spec: &PackageSpec,
+    ) -> Result<Vec<u8>, PackageError>;
+}
+
+/// Fetches `@preview` packages from a Typst package registry over
+/// HTTP.
+#[cfg(feature = "download")]
+#[derive(Debug, Clone)]
+pub struct RegistryFetcher {
+    /// Base url of the registry. Defaults to
+    /// `https://packages.typst.org`.
+    pub url: String,
+}
+
+#[cfg(feature = "download")]
+impl Default for RegistryFetcher {
+    fn default() -> Self {
+        Self {
+            url: "https://packages.typst.org".to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "download")]
+impl PackageFetcher for RegistryFetcher {
+    fn fetch(
+        &self,
+        spec: &PackageSpec,
+    ) -> Result<Vec<u8>, PackageError> {
+        use std::io::Read;
+
+        // Only `@preview` packages are hosted on the registry.
+        if spec.namespace != "preview" {
+            return Err(PackageError::NotFound(spec.clone()));
+        }
+
+        let url = format!(
+            "{}/{}/{}-{}.tar.gz",
+            self.url, spec.namespace, spec.name, spec.version,
+        );
+
+        info!("Downloading typst package {spec} from {url}");
+
+        let response =
+            ureq::get(&url).call().map_err(|e| match e {
+                ureq::Error::Status(404, _) => {
+                    PackageError::NotFound(spec.clone())
+                }
+                e => PackageError::NetworkFailed(Some(eco_format!(
+                    "{e}"
+                ))),
+            })?;
+
+        let mut body = Vec::new();
+        response.into_reader().read_to_end(&mut body).map_err(
+            |e| PackageError::NetworkFailed(Some(eco_format!("{e}"))),
+        )?;
+
+        Ok(body)
+    }
+}
+
+/// Fetches package archives from a directory laid out as
+/// `{namespace}/{name}-{version}.tar.gz`, like the official registry.
+#[derive(Debug, Clone)]
+pub struct DirFetcher(pub PathBuf);
+
+impl PackageFetcher for DirFetcher {
+    fn fetch(
+        &self,
+        spec: &PackageSpec,
+    ) -> Result<Vec<u8>, PackageError> {
+        let path = self
+            .0
+            .join(spec.namespace.as_str())
+            .join(format!("{}-{}.tar.gz", spec.name, spec.version));
+
+        fs::read(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                PackageError::NotFound(spec.clone())
+            }
+            _ => PackageError::Other(Some(eco_format!("{e}"))),
+        })
+    }
+}
+
+/// Expected [BLAKE3](blake3) hashes of package archives.
+///
+/// Archives of packages without an entry are accepted as is, unless
+/// [`Self::strict`] is set. In strict mode, packages already in the
+/// download cache are only used if they were extracted from an
+/// archive matching their entry.
+///
+/// Parses from and formats to lines of `{spec} {hex-hash}`, e.g.
+/// `@preview/example:0.1.0 af1349b9…`. Empty lines and lines starting
+/// with `#` are ignored.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PackageLock {
+    pub hashes: HashMap<PackageSpec, EcoString>,
+    /// Reject packages that have no entry.
+    pub strict: bool,
+}
+
+impl PackageLock {
+    /// The hex encoded hash of a package archive, as stored in the
+    /// lock.
+    pub fn hash(archive: &[u8]) -> EcoString {
+        blake3::hash(archive).to_hex().as_str().into()
+    }
+
+    /// Record the hash of a package archive.
+    pub fn insert(&mut self, spec: PackageSpec, archive: &[u8]) {
+        self.hashes.insert(spec, Self::hash(archive));
+    }
+
+    /// Check a package archive against its expected hash.
+    pub fn verify(
+        &self,
+        spec: &PackageSpec,
+        archive: &[u8],
+    ) -> Result<(), PackageError> {
+        let Some(expected) = self.expected(spec)? else {
+            return Ok(());
+        };
+
+        let found = Self::hash(archive);
+        if found.eq_ignore_ascii_case(expected) {
+            Ok(())
+        } else {
+            Err(PackageError::Other(Some(eco_format!(
+                "hash mismatch for {spec}: \
+                 expected {expected}, found {found}"
+            ))))
+        }
+    }
+
+    /// The expected hash of a package, failing if it has no entry
+    /// in strict mode.
+    fn expected(
+        &self,
+        spec: &PackageSpec,
+    ) -> Result<Option<&EcoString>, PackageError> {
+        match self.hashes.get(spec) {
+            Some(expected) => Ok(Some(expected)),
+            None if self.strict => {
+                Err(PackageError::Other(Some(eco_format!(
+                    "{spec} is missing from the package lock"
+                ))))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Whether a cached package directory can be used as is.
+    ///
+    /// In strict mode, the hash recorded when the package was
+    /// extracted must match the expected one.
+    fn accepts_cached(
+        &self,
+        spec: &PackageSpec,
+        package_dir: &Path,
+    ) -> Result<bool, PackageError> {
+        if !self.strict {
+            return Ok(true);
+        }
+
+        let expected = self.expected(spec)?;
+        let found =
+            fs::read_to_string(archive_hash_path(package_dir));
+        Ok(expected.zip(found.ok()).is_some_and(
+            |(expected, found)| {
+                found.trim().eq_ignore_ascii_case(expected)
+            },
+        ))
+    }
+}
+
+impl FromStr for PackageLock {
+    type Err = EcoString;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lock = Self::default();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (spec, hash) =
+                line.split_once(char::is_whitespace).ok_or_else(
+                    || eco_format!("line {}: missing hash", i + 1),
+                )?;
+            let spec = spec
+                .parse::<PackageSpec>()
+                .map_err(|e| eco_format!("line {}: {e}", i + 1))?;
+
+            lock.hashes.insert(spec, hash.trim().into());
+        }
+
+        Ok(lock)
+    }
+}
+
+impl fmt::Display for PackageLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut entries = self
+            .hashes
+            .iter()
+            .map(|(spec, hash)| (spec.to_string(), hash))
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        for (spec, hash) in entries {
+            writeln!(f, "{spec} {hash}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the local directory of a package, fetching it first if it
+/// is not present in any of the roots and fetching is enabled.
+pub(super) fn prepare_package(
+    spec: &PackageSpec,
+    roots: &TypstPackageRoots,
+    download: &TypstPackageDownload,
+) -> FileResult<PathBuf> {
+    // Skip the download cache, whose packages are checked against the
+    // lock below.
+    if let Some(package_dir) = roots
+        .iter()
+        .filter(|root| **root != download.cache_dir)
+        .map(|root| package_subdir(root, spec))
+        .find(|dir| dir.is_dir())
+    {
+        return Ok(package_dir);
+    }
+
+    let package_dir = package_subdir(&download.cache_dir, spec);
+    let cached = package_dir.is_dir();
+    if cached
+        && download
+            .lock
+            .accepts_cached(spec, &package_dir)
+            .map_err(FileError::Package)?
+    {
+        return Ok(package_dir);
+    }
+
+    let Some(fetcher) = &download.fetcher else {
+        if cached {
+            return Err(FileError::Package(stale_package(spec)));
+        }
+        return Err(FileError::Package(PackageError::NotFound(
+            spec.clone(),
+        )));
+    };
+
+    if !download.enabled {
+        if cached {
+            return Err(FileError::Package(stale_package(spec)));
+        }
+
+        return Err(FileError::Package(PackageError::Other(Some(
+            eco_format!(
+                "package downloading is disabled; \
+                 enable it via `TypstPackageDownload` or vendor \
+                 {spec} in one of the `TypstPackageRoots`"
+            ),
+        ))));
+    }
+
+    // Fail early on a missing entry in strict mode.
+    download.lock.expected(spec).map_err(FileError::Package)?;
+    let archive = fetcher.fetch(spec).map_err(FileError::Package)?;
+    download
+        .lock
+        .verify(spec, &archive)
+        .map_err(FileError::Package)?;
+    extract_package(&archive, &package_dir)
+        .map_err(FileError::Package)?;
+    fs::write(
+        archive_hash_path(&package_dir),
+        PackageLock::hash(&archive).as_bytes(),
+    )
+    .map_err(|e| FileError::from_io(e, &package_dir))?;
+
+    Ok(package_dir)
+}
+
+/// The error for a cached package that does not match the lock and
+/// cannot be fetched again.
+fn stale_package(spec: &PackageSpec) -> PackageError {
+    PackageError::Other(Some(eco_format!(
+        "cached {spec} does not match the package lock"
+    )))
+}
+
+/// The file next to a package directory that records the hash of
+/// the archive it was extracted from.
+fn archive_hash_path(package_dir: &Path) -> PathBuf {
+    let mut name =
+        package_dir.file_name().unwrap_or_default().to_owned();
+    name.push(".blake3");
+    package_dir.with_file_name(name)
+}
+
+/// The directory of a package within a package root.
+fn package_subdir(root: &Path, spec: &PackageSpec) -> PathBuf {
+    root.join(spec.namespace.as_str())
+        .join(spec.name.as_str())
+        .join(spec.version.to_string())
+}
+
+/// Extracts a gzipped tarball into `dest`.
+///
+/// The archive is unpacked into a temporary sibling directory first
+/// and then renamed, so that `dest` never holds a partial package.
+/// An existing `dest` is moved aside and only removed once the new
+/// package is in place.
+fn extract_package(
+    archive: &[u8],
+    dest: &Path,
+) -> Result<(), PackageError> {
+    let other = |e: std::io::Error| {
+        PackageError::Other(Some(eco_format!("{e}")))
+    };
+
+    let parent = dest.parent().ok_or_else(|| {
+        PackageError::Other(Some("invalid package directory".into()))
+    })?;
+    fs::create_dir_all(parent).map_err(other)?;
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let sibling = |kind: &str| {
+        parent.join(format!(
+            ".{kind}-{}-{}-{nanos}",
+            dest.file_name().unwrap_or_default().to_string_lossy(),
+            std::process::id(),
+        ))
+    };
+    let tmp = sibling("tmp");
+
+    let decoder = flate2::read::GzDecoder::new(archive);
+    if let Err(e) = tar::Archive::new(decoder).unpack(&tmp) {
+        let _ = fs::remove_dir_all(&tmp);
+        return Err(PackageError::MalformedArchive(Some(
+            eco_format!("{e}"),
+        )));
+    }
+
+    let old = dest.is_dir().then(|| sibling("old"));
+    if let Some(old) = &old
+        && let Err(e) = fs::rename(dest, old)
+    {
+        let _ = fs::remove_dir_all(&tmp);
+        return Err(other(e));
+    }
+
+    if let Err(e) = fs::rename(&tmp, dest) {
+        let _ = fs::remove_dir_all(&tmp);
+        // Another process may have extracted the package meanwhile.
+        if !dest.is_dir() {
+            if let Some(old) = &old {
+                let _ = fs::rename(old, dest);
+            }
+            return Err(other(e));
+        }
+    }
+    if let Some(old) = &old {
+        let _ = fs::remove_dir_all(old);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// A [`PackageFetcher`] that counts the archives it serves from a
+    /// [`DirFetcher`].
+    struct CountingFetcher {
+        inner: DirFetcher,
+        count: AtomicUsize,
+    }
+
+    impl PackageFetcher for CountingFetcher {
+        fn fetch(
+            &self,
+            spec: &PackageSpec,
+        ) -> Result<Vec<u8>, PackageError> {
+            self.count.fetch_add(1, Ordering::Relaxed);
+            self.inner.fetch(spec)
+        }
+    }
+
+    struct Setup {
+        _dir: tempfile::TempDir,
+        spec: PackageSpec,
+        archive: Vec<u8>,
+        fetcher: Arc<CountingFetcher>,
+        download: TypstPackageDownload,
+    }
+
+    impl Setup {
+        /// A registry holding an archive of `@preview/example:0.1.0`.
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let spec = "@preview/example:0.1.0"
+                .parse::<PackageSpec>()
+                .unwrap();
+            let archive = archive(&[
+                ("typst.toml", "[package]"),
+                ("lib.typ", "#let x = 1"),
+            ]);
+
+            let registry = dir.path().join("registry");
+            fs::create_dir_all(registry.join("preview")).unwrap();
+            fs::write(
+                registry.join("preview").join("example-0.1.0.tar.gz"),
+                &archive,
+            )
+            .unwrap();
+
+            let fetcher = Arc::new(CountingFetcher {
+                inner: DirFetcher(registry),
+                count: AtomicUsize::new(0),
+            });
+            let download = TypstPackageDownload {
+                enabled: true,
+                cache_dir: dir.path().join("cache"),
+                fetcher: Some(fetcher.clone()),
+                lock: PackageLock::default(),
+            };
+
+            Self {
+                _dir: dir,
+                spec,
+                archive,
+                fetcher,
+                download,
+            }
+        }
+
+        fn prepare(&self) -> FileResult<PathBuf> {
+            prepare_package(
+                &self.spec,
+                &TypstPackageRoots(Vec::new()),
+                &self.download,
+            )
+        }
+
+        fn fetches(&self) -> usize {
+            self.fetcher.count.load(Ordering::Relaxed)
+        }
+
+        /// Entries of the cache directory of the package's name.
+        fn cache_entries(&self) -> Vec<String> {
+            let dir = self
+                .download
+                .cache_dir
+                .join("preview")
+                .join("example");
+            let Ok(entries) = fs::read_dir(dir) else {
+                return Vec::new();
+            };
+            let mut entries = entries
+                .map(|entry| {
+                    entry.unwrap().file_name().into_string().unwrap()
+                })
+                .collect::<Vec<_>>();
+            entries.sort();
+            entries
+        }
+    }
+
+    /// A gzipped tarball of the given files.
+    fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn lock_mismatch_is_rejected() {
+        let mut setup = Setup::new();
+        setup
+            .download
+            .lock
+            .insert(setup.spec.clone(), b"another archive");
+
+        let err = setup.prepare().unwrap_err();
+        assert!(err.to_string().contains("hash mismatch"));
+        assert!(setup.cache_entries().is_empty());
+    }
+
+    #[test]
+    fn strict_lock_requires_entry() {
+        let mut setup = Setup::new();
+        setup.download.lock.strict = true;
+
+        let err = setup.prepare().unwrap_err();
+        assert!(
+            err.to_string().contains("missing from the package lock")
+        );
+        assert_eq!(setup.fetches(), 0);
+    }
+
+    #[test]
+    fn strict_lock_verifies_cache_hits() {
+        let mut setup = Setup::new();
+        setup.download.lock.strict = true;
+        setup
+            .download
+            .lock
+            .insert(setup.spec.clone(), &setup.archive.clone());
+
+        let package_dir = setup.prepare().unwrap();
+        assert!(package_dir.join("lib.typ").is_file());
+        assert_eq!(setup.fetches(), 1);
+
+        // A verified cache hit is not fetched again.
+        setup.prepare().unwrap();
+        assert_eq!(setup.fetches(), 1);
+
+        // A cached package that doesn't match the lock is replaced.
+        fs::write(package_dir.join("lib.typ"), "tampered").unwrap();
+        fs::write(archive_hash_path(&package_dir), "tampered")
+            .unwrap();
+        setup.prepare().unwrap();
+        assert_eq!(setup.fetches(), 2);
+        assert_eq!(
+            fs::read_to_string(package_dir.join("lib.typ")).unwrap(),
+            "#let x = 1"
+        );
+
+        // And rejected if it can't be fetched again.
+        fs::write(archive_hash_path(&package_dir), "tampered")
+            .unwrap();
+        setup.download.enabled = false;
+        let err = setup.prepare().unwrap_err();
+        assert!(err.to_string().contains("does not match"));
+    }
+
+    #[test]
+    fn strict_lock_verifies_cache_in_default_roots() {
+        let mut setup = Setup::new();
+        setup.download.lock.strict = true;
+        setup
+            .download
+            .lock
+            .insert(setup.spec.clone(), &setup.archive.clone());
+
+        // The default roots include the default download cache, point
+        // it at the one of the test.
+        let default_cache = TypstPackageDownload::default().cache_dir;
+        let mut roots = TypstPackageRoots::default();
+        let root = roots
+            .iter_mut()
+            .find(|root| **root == default_cache)
+            .unwrap();
+        *root = setup.download.cache_dir.clone();
+        let prepare = |setup: &Setup| {
+            prepare_package(&setup.spec, &roots, &setup.download)
+        };
+
+        // A package in the cache without a recorded hash, e.g. one
+        // extracted by the Typst CLI, is fetched again.
+        let package_dir =
+            package_subdir(&setup.download.cache_dir, &setup.spec);
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(package_dir.join("lib.typ"), "tampered").unwrap();
+        assert_eq!(prepare(&setup).unwrap(), package_dir);
+        assert_eq!(setup.fetches(), 1);
+        assert_eq!(
+            fs::read_to_string(package_dir.join("lib.typ")).unwrap(),
+            "#let x = 1"
+        );
+
+        // A verified cache hit is served from the roots.
+        prepare(&setup).unwrap();
+        assert_eq!(setup.fetches(), 1);
+
+        // A tampered one is not.
+        fs::write(archive_hash_path(&package_dir), "tampered")
+            .unwrap();
+        setup.download.enabled = false;
+        let err = prepare(&setup).unwrap_err();
+        assert!(err.to_string().contains("does not match"));
+    }
+
+    #[test]
+    fn extraction_replaces_existing_package() {
+        let setup = Setup::new();
+        let dest =
+            package_subdir(&setup.download.cache_dir, &setup.spec);
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("stale.typ"), "").unwrap();
+
+        extract_package(&setup.archive, &dest).unwrap();
+        assert!(dest.join("lib.typ").is_file());
+        assert!(!dest.join("stale.typ").exists());
+        // Neither the temporary nor the old directory is left behind.
+        assert_eq!(setup.cache_entries(), ["0.1.0"]);
+    }
+
+    #[test]
+    fn malformed_archive_leaves_no_partial_package() {
+        let setup = Setup::new();
+        let mut archive = setup.archive.clone();
+        archive.truncate(archive.len() / 2);
+        let dest =
+            package_subdir(&setup.download.cache_dir, &setup.spec);
+
+        let err = extract_package(&archive, &dest).unwrap_err();
+        assert!(matches!(err, PackageError::MalformedArchive(_)));
+        assert!(setup.cache_entries().is_empty());
+    }
+}
@@ -0,0 +1,525 @@
+//! Generate [`TypstFunc`] bindings from `.typ` files in build
+//! scripts.
+//!
+//! Every top-level `#let` closure becomes a struct deriving
+//! [`TypstFunc`]. Positional parameters become [`Value`] fields,
+//! while named parameters become `Option` fields typed from their
+//! default values where possible, so that `None` keeps the Typst
+//! default.
+//!
+//! ```typ
+//! #let button(body, interaction_state, fill: base7, size: 16pt) = ..
+//! ```
+//!
+//! generates
+//!
+//! ```ignore
+//! #[derive(::velyst::func::TypstFunc, Default, Clone, Debug)]
+//! #[typst(name = "button")]
+//! pub struct ButtonFunc {
+//!     pub body: ::velyst::typst::foundations::Value,
+//!     pub interaction_state: ::velyst::typst::foundations::Value,
+//!     #[typst(named)]
+//!     pub fill: Option<::velyst::typst::foundations::Value>,
+//!     #[typst(named)]
+//!     pub size: Option<::velyst::typst::layout::Length>,
+//! }
+//! ```
+//!
+//! # Usage
+//!
+//! In `build.rs`:
+//!
+//! ```no_run
+//! velyst_build::generate("assets/typst/game_ui.typ");
+//! ```
+//!
+//! And in the crate:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/game_ui.rs"));
+//! ```
+//!
+//! [`TypstFunc`]: https://docs.rs/velyst/latest/velyst/func/trait.TypstFunc.html
+//! [`Value`]: https://docs.rs/typst/latest/typst/foundations/enum.Value.html
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use typst_syntax::ast::{self, AstNode};
+
+const VALUE: &str = "::velyst::typst::foundations::Value";
+
+/// Generate bindings for a `.typ` file into `$OUT_DIR/<name>.rs`,
+/// where `<name>` is the file stem, and rerun the build script
+/// whenever the file changes.
+///
+/// # Panics
+///
+/// If the file cannot be read or parsed, its names collide in Rust,
+/// or the bindings cannot be written. Use [`try_generate`] to handle
+/// errors instead.
+pub fn generate(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    try_generate(path).unwrap_or_else(|err| {
+        panic!("Unable to generate bindings for {path:?}: {err}")
+    })
+}
+
+/// Fallible version of [`generate`], returning the path of the
+/// generated file.
+pub fn try_generate(path: impl AsRef<Path>) -> io::Result<PathBuf> {
+    let path = path.as_ref();
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let text = fs::read_to_string(path)?;
+    let bindings = bindings(&text).map_err(|message| {
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    })?;
+
+    let out_dir = env::var_os("OUT_DIR").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "OUT_DIR is not set, call this from a build script",
+        )
+    })?;
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the path has no valid file name",
+            )
+        })?;
+
+    let out_path = Path::new(&out_dir).join(format!("{stem}.rs"));
+    fs::write(&out_path, bindings)?;
+    Ok(out_path)
+}
+
+/// Generate bindings for the top-level closures of a Typst source.
+///
+/// Returns the first syntax error, if any, or an error if two
+/// functions map to the same struct name or two parameters of a
+/// function to the same field name, e.g. `my-button` and
+/// `my_button`.
+pub fn bindings(text: &str) -> Result<String, String> {
+    let root = typst_syntax::parse(text);
+    let (errors, _) = root.errors_and_warnings();
+    if let Some(error) = errors.into_iter().next() {
+        return Err(format!("syntax error: {}", error.message));
+    }
+
+    let Some(markup) = root.cast::<ast::Markup>() else {
+        return Ok(String::new());
+    };
+
+    // Later definitions shadow earlier ones.
+    let mut funcs = Vec::<Binding>::new();
+    for expr in markup.exprs() {
+        let ast::Expr::LetBinding(binding) = expr else {
+            continue;
+        };
+        let (
+            ast::LetBindingKind::Closure(name),
+            Some(ast::Expr::Closure(closure)),
+        ) = (binding.kind(), binding.init())
+        else {
+            continue;
+        };
+        if name.as_str() == "_" {
+            continue;
+        }
+
+        let binding = Binding::new(name.as_str(), closure)?;
+        funcs.retain(|func| func.name != binding.name);
+        funcs.push(binding);
+    }
+
+    for (i, func) in funcs.iter().enumerate() {
+        if let Some(other) = funcs[..i]
+            .iter()
+            .find(|other| other.struct_name == func.struct_name)
+        {
+            return Err(format!(
+                "`{}` and `{}` both generate `{}`",
+                other.name, func.name, func.struct_name
+            ));
+        }
+    }
+
+    let mut out =
+        String::from("// Generated by velyst_build, do not edit.\n");
+    for func in funcs.iter() {
+        out.push('\n');
+        func.write(&mut out);
+    }
+    Ok(out)
+}
+
+/// A top-level closure of a Typst source.
+struct Binding {
+    name: String,
+    struct_name: String,
+    signature: String,
+    params: Vec<Param>,
+}
+
+struct Param {
+    /// The field name in Rust.
+    field: String,
+    /// The Typst name of a named parameter.
+    named: Option<String>,
+    ty: &'static str,
+}
+
+impl Binding {
+    fn new(
+        name: &str,
+        closure: ast::Closure,
+    ) -> Result<Self, String> {
+        let mut params = Vec::new();
+        for (i, param) in closure.params().children().enumerate() {
+            match param {
+                ast::Param::Pos(pattern) => {
+                    let field = match pattern {
+                        ast::Pattern::Normal(ast::Expr::Ident(
+                            ident,
+                        )) => rust_ident(ident.as_str()),
+                        _ => None,
+                    }
+                    .unwrap_or_else(|| format!("arg{i}"));
+
+                    push_param(
+                        name,
+                        &mut params,
+                        Param {
+                            field,
+                            named: None,
+                            ty: VALUE,
+                        },
+                    )?;
+                }
+                ast::Param::Named(named) => {
+                    let param = named.name().as_str();
+                    push_param(
+                        name,
+                        &mut params,
+                        Param {
+                            field: rust_ident(param)
+                                .unwrap_or_else(|| format!("arg{i}")),
+                            named: Some(param.to_owned()),
+                            ty: infer_type(named.expr()),
+                        },
+                    )?;
+                }
+                // Variadic arguments cannot be passed.
+                ast::Param::Spread(_) => {}
+            }
+        }
+
+        let signature = closure
+            .params()
+            .to_untyped()
+            .full_text()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Ok(Self {
+            name: name.to_owned(),
+            struct_name: struct_name(name),
+            signature,
+            params,
+        })
+    }
+
+    fn write(&self, out: &mut String) {
+        let Self {
+            name,
+            struct_name,
+            signature,
+            params,
+        } = self;
+
+        // Writing into a `String` never fails.
+        let _ = writeln!(out, "/// Binding for `{name}{signature}`.");
+        let _ = writeln!(
+            out,
+            "#[derive(::velyst::func::TypstFunc, Default, Clone, Debug)]"
+        );
+        let _ = writeln!(out, "#[typst(name = {name:?})]");
+        // Not every binding is necessarily used.
+        let _ = writeln!(out, "#[allow(dead_code)]");
+        let _ = writeln!(out, "pub struct {struct_name} {{");
+        for param in params.iter() {
+            match &param.named {
+                Some(named) => {
+                    if *named == param.field {
+                        let _ = writeln!(out, "    #[typst(named)]");
+                    } else {
+                        let _ = writeln!(
+                            out,
+                            "    #[typst(named, rename = {named:?})]"
+                        );
+                    }
+                    let _ = writeln!(
+                        out,
+                        "    pub {}: Option<{}>,",
+                        param.field, param.ty
+                    );
+                }
+                None => {
+                    let _ = writeln!(
+                        out,
+                        "    pub {}: {},",
+                        param.field, param.ty
+                    );
+                }
+            }
+        }
+        let _ = writeln!(out, "}}");
+    }
+}
+
+/// Add a parameter of the function `name`, unless another parameter
+/// already maps to the same field name.
+fn push_param(
+    name: &str,
+    params: &mut Vec<Param>,
+    param: Param,
+) -> Result<(), String> {
+    if params.iter().any(|other| other.field == param.field) {
+        return Err(format!(
+            "two parameters of `{name}` generate the field `{}`",
+            param.field
+        ));
+    }
+    params.push(param);
+    Ok(())
+}
+
+/// The Rust type of a parameter with the given default value.
+fn infer_type(expr: ast::Expr) -> &'static str {
+    match expr {
+        ast::Expr::Bool(_) => "bool",
+        ast::Expr::Int(_) => "i64",
+        ast::Expr::Float(_) => "f64",
+        ast::Expr::Str(_) => "String",
+        ast::Expr::Numeric(numeric) => match numeric.get().1 {
+            ast::Unit::Pt
+            | ast::Unit::Mm
+            | ast::Unit::Cm
+            | ast::Unit::In
+            | ast::Unit::Em => "::velyst::typst::layout::Length",
+            ast::Unit::Rad | ast::Unit::Deg => {
+                "::velyst::typst::layout::Angle"
+            }
+            ast::Unit::Fr => "::velyst::typst::layout::Fr",
+            ast::Unit::Percent => "::velyst::typst::layout::Ratio",
+        },
+        ast::Expr::ContentBlock(_) => {
+            "::velyst::typst::foundations::Content"
+        }
+        ast::Expr::Array(_) => "::velyst::typst::foundations::Array",
+        ast::Expr::Dict(_) => "::velyst::typst::foundations::Dict",
+        ast::Expr::Parenthesized(parenthesized) => {
+            infer_type(parenthesized.expr())
+        }
+        ast::Expr::Unary(unary) => match unary.op() {
+            ast::UnOp::Not => "bool",
+            ast::UnOp::Pos | ast::UnOp::Neg => {
+                match infer_type(unary.expr()) {
+                    ty @ ("i64"
+                    | "f64"
+                    | "::velyst::typst::layout::Length"
+                    | "::velyst::typst::layout::Angle"
+                    | "::velyst::typst::layout::Fr"
+                    | "::velyst::typst::layout::Ratio") => ty,
+                    _ => VALUE,
+                }
+            }
+        },
+        _ => VALUE,
+    }
+}
+
+/// The Rust field name of a Typst identifier, e.g. `font_size` for
+/// `font-size`.
+fn rust_ident(name: &str) -> Option<String> {
+    let ident = name.replace('-', "_");
+    if ident == "_"
+        || !ident.chars().all(|c| c == '_' || c.is_alphanumeric())
+    {
+        return None;
+    }
+
+    Some(match ident.as_str() {
+        // Cannot be raw identifiers.
+        "self" | "Self" | "super" | "crate" => format!("{ident}_"),
+        ident if is_keyword(ident) => format!("r#{ident}"),
+        _ => ident,
+    })
+}
+
+fn is_keyword(ident: &str) -> bool {
+    matches!(
+        ident,
+        "as" | "async"
+            | "await"
+            | "break"
+            | "const"
+            | "continue"
+            | "dyn"
+            | "else"
+            | "enum"
+            | "extern"
+            | "false"
+            | "fn"
+            | "for"
+            | "gen"
+            | "if"
+            | "impl"
+            | "in"
+            | "let"
+            | "loop"
+            | "match"
+            | "mod"
+            | "move"
+            | "mut"
+            | "pub"
+            | "ref"
+            | "return"
+            | "static"
+            | "struct"
+            | "trait"
+            | "true"
+            | "type"
+            | "unsafe"
+            | "use"
+            | "where"
+            | "while"
+            | "abstract"
+            | "become"
+            | "box"
+            | "do"
+            | "final"
+            | "macro"
+            | "override"
+            | "priv"
+            | "try"
+            | "typeof"
+            | "unsized"
+            | "virtual"
+            | "yield"
+    )
+}
+
+/// The struct name of a Typst function, e.g. `PerfMetricsFunc` for
+/// `perf_metrics`.
+fn struct_name(name: &str) -> String {
+    let mut out = String::new();
+    for segment in name.split(['-', '_']).filter(|s| !s.is_empty()) {
+        let mut chars = segment.chars();
+        if let Some(first) = chars.next() {
+            out.extend(first.to_uppercase());
+            out.push_str(chars.as_str());
+        }
+    }
+    if !out.starts_with(|c: char| c.is_alphabetic()) {
+        out.insert(0, 'F');
+    }
+    out.push_str("Func");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_params_are_typed_from_defaults() {
+        let out = bindings(
+            "#let button(body, state, fill: red, size: 16pt, \
+             font-size: -1.5, label: [Hi], on: not false) = body",
+        )
+        .unwrap();
+
+        assert!(out.contains("#[typst(name = \"button\")]"));
+        assert!(out.contains("pub struct ButtonFunc {"));
+        assert!(out.contains(
+            "    pub body: ::velyst::typst::foundations::Value,\n    \
+             pub state: ::velyst::typst::foundations::Value,"
+        ));
+        assert!(out.contains(
+            "    pub fill: Option<::velyst::typst::foundations::Value>,"
+        ));
+        assert!(out.contains(
+            "    pub size: Option<::velyst::typst::layout::Length>,"
+        ));
+        assert!(out.contains(
+            "    #[typst(named, rename = \"font-size\")]\n    \
+             pub font_size: Option<f64>,"
+        ));
+        assert!(out.contains(
+            "    pub label: Option<::velyst::typst::foundations::Content>,"
+        ));
+        assert!(out.contains("    pub on: Option<bool>,"));
+    }
+
+    #[test]
+    fn only_top_level_closures() {
+        let out = bindings(
+            "#let x = 1\n\
+             #let perf_metrics(fps, ..rest) = fps\n\
+             #{ let nested(a) = a }\n\
+             #let perf_metrics(type, (a, b)) = a",
+        )
+        .unwrap();
+
+        assert_eq!(out.matches("pub struct").count(), 1);
+        assert!(out.contains("pub struct PerfMetricsFunc {"));
+        assert!(out.contains("pub r#type:"));
+        assert!(out.contains("pub arg1:"));
+        assert!(!out.contains("fps"));
+        assert!(!out.contains("Nested"));
+    }
+
+    #[test]
+    fn colliding_struct_names_are_reported() {
+        assert_eq!(
+            bindings("#let my-button() = []\n#let my_button() = []"),
+            Err("`my-button` and `my_button` both generate \
+                 `MyButtonFunc`"
+                .into())
+        );
+        assert_eq!(
+            bindings("#let button() = []\n#let Button() = []"),
+            Err("`button` and `Button` both generate `ButtonFunc`"
+                .into())
+        );
+        // Shadowed definitions don't collide.
+        assert!(
+            bindings("#let button() = []\n#let button(a) = a")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn colliding_field_names_are_reported() {
+        assert_eq!(
+            bindings("#let f(font-size: 1pt, font_size: 2pt) = []"),
+            Err("two parameters of `f` generate the field \
+                 `font_size`"
+                .into())
+        );
+        assert!(bindings("#let f(arg1, (a, b)) = []").is_err());
+    }
+
+    #[test]
+    fn syntax_errors_are_reported() {
+        assert!(bindings("#let f(a = 1").is_err());
+    }
+}
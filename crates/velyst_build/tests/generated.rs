@@ -0,0 +1,69 @@
+//! Compile the bindings generated for `generated/game_ui.typ`.
+//!
+//! Regenerate `generated/game_ui.rs` after changing the generator
+//! with `VELYST_BUILD_OVERWRITE=1 cargo test -p velyst_build`.
+
+use velyst::func::TypstFunc;
+use velyst::typst::foundations::Value;
+use velyst::typst::layout::{Abs, Length};
+
+mod game_ui {
+    include!("generated/game_ui.rs");
+}
+
+const SOURCE: &str = include_str!("generated/game_ui.typ");
+const GENERATED: &str = include_str!("generated/game_ui.rs");
+
+#[test]
+fn generated_bindings_are_up_to_date() {
+    let bindings = velyst_build::bindings(SOURCE).unwrap();
+    if std::env::var_os("VELYST_BUILD_OVERWRITE").is_some() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/generated/game_ui.rs"
+        );
+        std::fs::write(path, &bindings).unwrap();
+    } else {
+        assert_eq!(bindings, GENERATED);
+    }
+}
+
+#[test]
+fn generated_bindings_pass_args() {
+    let button = game_ui::ButtonFunc {
+        body: Value::Str("Play".into()),
+        size: Some(Length::from(Abs::pt(12.0))),
+        ..Default::default()
+    };
+    assert_eq!(game_ui::ButtonFunc::NAME, "button");
+
+    let mut positional = Vec::new();
+    button.apply_positional_args(&mut positional);
+    assert_eq!(positional, [Value::Str("Play".into()), Value::None]);
+
+    let mut named = Vec::new();
+    button.apply_named_args(&mut named);
+    assert_eq!(
+        named,
+        [("size", Value::Length(Abs::pt(12.0).into()))]
+    );
+
+    let bar = game_ui::HealthBarFunc {
+        font_size: Some(Length::from(Abs::pt(8.0))),
+        ..Default::default()
+    };
+    let mut named = Vec::new();
+    bar.apply_named_args(&mut named);
+    assert_eq!(
+        named,
+        [("font-size", Value::Length(Abs::pt(8.0).into()))]
+    );
+
+    let metrics = game_ui::PerfMetricsFunc {
+        r#type: Some("frame-time".into()),
+        ..Default::default()
+    };
+    let mut named = Vec::new();
+    metrics.apply_named_args(&mut named);
+    assert_eq!(named, [("type", Value::Str("frame-time".into()))]);
+}
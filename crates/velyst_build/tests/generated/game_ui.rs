@@ -0,0 +1,36 @@
+// Generated by velyst_build, do not edit.
+
+/// Binding for `button(body, interaction-state, fill: base7, size: 16pt)`.
+#[derive(::velyst::func::TypstFunc, Default, Clone, Debug)]
+#[typst(name = "button")]
+#[allow(dead_code)]
+pub struct ButtonFunc {
+    pub body: ::velyst::typst::foundations::Value,
+    pub interaction_state: ::velyst::typst::foundations::Value,
+    #[typst(named)]
+    pub fill: Option<::velyst::typst::foundations::Value>,
+    #[typst(named)]
+    pub size: Option<::velyst::typst::layout::Length>,
+}
+
+/// Binding for `perf_metrics(fps, type: "fps", ..rest)`.
+#[derive(::velyst::func::TypstFunc, Default, Clone, Debug)]
+#[typst(name = "perf_metrics")]
+#[allow(dead_code)]
+pub struct PerfMetricsFunc {
+    pub fps: ::velyst::typst::foundations::Value,
+    #[typst(named, rename = "type")]
+    pub r#type: Option<String>,
+}
+
+/// Binding for `health-bar((current, max), width: 50%, font-size: 1em)`.
+#[derive(::velyst::func::TypstFunc, Default, Clone, Debug)]
+#[typst(name = "health-bar")]
+#[allow(dead_code)]
+pub struct HealthBarFunc {
+    pub arg0: ::velyst::typst::foundations::Value,
+    #[typst(named)]
+    pub width: Option<::velyst::typst::layout::Ratio>,
+    #[typst(named, rename = "font-size")]
+    pub font_size: Option<::velyst::typst::layout::Length>,
+}
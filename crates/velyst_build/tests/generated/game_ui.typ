@@ -0,0 +1,11 @@
+#let base7 = rgb("#bbb")
+
+#let button(body, interaction-state, fill: base7, size: 16pt) = {
+  box(fill: fill, text(size: size, body))
+}
+
+#let perf_metrics(fps, type: "fps", ..rest) = [#fps]
+
+#let health-bar((current, max), width: 50%, font-size: 1em) = {
+  box(width: width, text(size: font-size)[#current / #max])
+}
@@ -0,0 +1,464 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{ToTokens, quote};
+use syn::ext::IdentExt;
+use syn::spanned::Spanned;
+use syn::{
+    Data, DeriveInput, Expr, Field, GenericArgument, LitStr, Member,
+    Path, PathArguments, Type, parse_macro_input, parse_quote,
+    parse_quote_spanned,
+};
+
+/// Derive `velyst::func::TypstFunc` for a struct.
+///
+/// # Container attributes
+///
+/// - `#[typst(name = "...")]` _(required)_: The name of the function
+///   in the Typst scope, or a dotted path into nested modules.
+/// - `#[typst(crate = "...")]`: The path to the `velyst` crate, for
+///   when it is renamed or re-exported. Defaults to `::velyst`.
+///
+/// # Field attributes
+///
+/// - `#[typst(positional)]`: Pass the field as a positional argument,
+///   in declaration order. This is the default for fields without an
+///   attribute.
+/// - `#[typst(named)]`: Pass the field as a named argument. A field
+///   of type `Option<T>` is omitted when `None`, so that the Typst
+///   default applies.
+/// - `#[typst(rename = "...")]`: The Typst name of a named argument,
+///   e.g. `font-size`. Defaults to the field name.
+/// - `#[typst(default = ...)]`: The value passed when an `Option<T>`
+///   field is `None`.
+/// - `#[typst(skip)]`: Never pass the field to Typst.
+///
+/// Passed fields must implement `IntoValue` and `Clone`.
+#[proc_macro_derive(TypstFunc, attributes(typst))]
+pub fn derive_typst_func(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_typst_func(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_typst_func(
+    input: DeriveInput,
+) -> syn::Result<TokenStream2> {
+    let (name, krate) = parse_container_attrs(&input)?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        Data::Enum(data) => {
+            return Err(syn::Error::new(
+                data.enum_token.span,
+                "TypstFunc can only be derived for structs",
+            ));
+        }
+        Data::Union(data) => {
+            return Err(syn::Error::new(
+                data.union_token.span,
+                "TypstFunc can only be derived for structs",
+            ));
+        }
+    };
+
+    let mut errors = Vec::new();
+    let mut args = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        match parse_field(index, field, &krate) {
+            Ok(Some(arg)) => args.push(arg),
+            Ok(None) => {}
+            Err(err) => errors.push(err),
+        }
+    }
+    if let Some(err) = errors.into_iter().reduce(|mut acc, err| {
+        acc.combine(err);
+        acc
+    }) {
+        return Err(err);
+    }
+
+    let value_ty = quote!(#krate::typst::foundations::Value);
+
+    let mut generics = input.generics.clone();
+    let where_clause = generics.make_where_clause();
+    where_clause
+        .predicates
+        .push(parse_quote!(Self: Send + Sync + 'static));
+    for arg in args.iter() {
+        let ty = arg.value_ty();
+        where_clause.predicates.push(
+            parse_quote_spanned! {ty.span()=>
+                #ty: #krate::typst::foundations::IntoValue
+                    + ::core::clone::Clone
+            },
+        );
+    }
+
+    let positional_names = args
+        .iter()
+        .filter(|arg| matches!(arg.kind, ArgKind::Positional))
+        .map(|arg| match &arg.member {
+            Member::Named(ident) => ident.unraw().to_string(),
+            Member::Unnamed(index) => index.index.to_string(),
+        });
+    let named_names = args.iter().filter_map(|arg| match &arg.kind {
+        ArgKind::Named(name) => Some(name),
+        ArgKind::Positional => None,
+    });
+
+    let positional = args
+        .iter()
+        .filter(|arg| matches!(arg.kind, ArgKind::Positional))
+        .map(|arg| {
+            let value = arg.value_expr();
+            quote! { args.push(#value); }
+        });
+
+    let named = args
+        .iter()
+        .filter_map(|arg| match &arg.kind {
+            ArgKind::Named(name) => Some((arg, name)),
+            ArgKind::Positional => None,
+        })
+        .map(|(arg, name)| {
+            let member = &arg.member;
+            if arg.option.is_some() && arg.default.is_none() {
+                quote! {
+                    if let ::core::option::Option::Some(arg) =
+                        self.#member.as_ref()
+                    {
+                        args.push((
+                            #name,
+                            #krate::typst::foundations::IntoValue
+                                ::into_value(::core::clone::Clone::clone(arg)),
+                        ));
+                    }
+                }
+            } else {
+                let value = arg.value_expr();
+                quote! { args.push((#name, #value)); }
+            }
+        });
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) =
+        generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics #krate::func::TypstFunc
+            for #ident #ty_generics #where_clause
+        {
+            const NAME: &'static str = #name;
+
+            const PARAMS: ::core::option::Option<
+                #krate::func::TypstFuncParams,
+            > = ::core::option::Option::Some(
+                #krate::func::TypstFuncParams {
+                    positional: &[#(#positional_names),*],
+                    named: &[#(#named_names),*],
+                },
+            );
+
+            fn apply_positional_args(
+                &self,
+                args: &mut ::std::vec::Vec<#value_ty>,
+            ) {
+                args.clear();
+                #(#positional)*
+            }
+
+            fn apply_named_args(
+                &self,
+                args: &mut ::std::vec::Vec<(&'static str, #value_ty)>,
+            ) {
+                args.clear();
+                #(#named)*
+            }
+        }
+    })
+}
+
+/// Parse the `#[typst(...)]` attributes of the struct into the
+/// function name and the path to the `velyst` crate.
+fn parse_container_attrs(
+    input: &DeriveInput,
+) -> syn::Result<(LitStr, Path)> {
+    let mut name = None;
+    let mut krate = None;
+
+    for attr in input.attrs.iter() {
+        if !attr.path().is_ident("typst") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                if name.is_some() {
+                    return Err(
+                        meta.error("duplicate `name` attribute")
+                    );
+                }
+                name = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else if meta.path.is_ident("crate") {
+                if krate.is_some() {
+                    return Err(
+                        meta.error("duplicate `crate` attribute")
+                    );
+                }
+                let lit = meta.value()?.parse::<LitStr>()?;
+                krate = Some(lit.parse::<Path>()?);
+                Ok(())
+            } else {
+                Err(meta.error(
+                    "unknown attribute, expected `name = \"...\"` \
+                     or `crate = \"...\"`",
+                ))
+            }
+        })?;
+    }
+
+    let name = name.ok_or_else(|| {
+        syn::Error::new(
+            input.ident.span(),
+            "missing `#[typst(name = \"...\")]` attribute",
+        )
+    })?;
+    if name.value().is_empty() {
+        return Err(syn::Error::new(
+            name.span(),
+            "function name must not be empty",
+        ));
+    }
+
+    Ok((name, krate.unwrap_or_else(|| parse_quote!(::velyst))))
+}
+
+enum ArgKind {
+    Positional,
+    Named(LitStr),
+}
+
+/// A field that is passed to Typst.
+struct FieldArg {
+    member: Member,
+    kind: ArgKind,
+    /// The inner type of an `Option<T>` field.
+    option: Option<Type>,
+    ty: Type,
+    default: Option<Expr>,
+    /// The path to the `velyst` crate.
+    krate: Path,
+}
+
+impl FieldArg {
+    /// The type converted into a Typst value.
+    fn value_ty(&self) -> &Type {
+        match (&self.option, &self.default) {
+            (Some(inner), Some(_)) => inner,
+            (Some(inner), None)
+                if matches!(self.kind, ArgKind::Named(_)) =>
+            {
+                inner
+            }
+            _ => &self.ty,
+        }
+    }
+
+    /// An expression of the argument value.
+    fn value_expr(&self) -> TokenStream2 {
+        let krate = &self.krate;
+        let member = &self.member;
+        let value =
+            quote!(::core::clone::Clone::clone(&self.#member));
+        let value = match &self.default {
+            Some(default) => {
+                let ty = self.value_ty();
+                quote! {
+                    match #value {
+                        ::core::option::Option::Some(value) => value,
+                        ::core::option::Option::None => {
+                            let default: #ty = #default;
+                            default
+                        }
+                    }
+                }
+            }
+            None => value,
+        };
+
+        quote! {
+            #krate::typst::foundations::IntoValue::into_value(#value)
+        }
+    }
+}
+
+/// Parse the `#[typst(...)]` attributes of a field, returning `None`
+/// for skipped fields.
+fn parse_field(
+    index: usize,
+    field: &Field,
+    krate: &Path,
+) -> syn::Result<Option<FieldArg>> {
+    let mut positional = None;
+    let mut named = None;
+    let mut skip = None;
+    let mut rename = None;
+    let mut default = None;
+
+    for attr in field.attrs.iter() {
+        if !attr.path().is_ident("typst") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            let path = &meta.path;
+            let duplicate = |set: bool| {
+                if set {
+                    Err(meta.error(format!(
+                        "duplicate `{}` attribute",
+                        path.to_token_stream()
+                    )))
+                } else {
+                    Ok(())
+                }
+            };
+
+            if path.is_ident("positional") {
+                duplicate(positional.is_some())?;
+                positional = Some(path.span());
+            } else if path.is_ident("named") {
+                duplicate(named.is_some())?;
+                named = Some(path.span());
+            } else if path.is_ident("skip") {
+                duplicate(skip.is_some())?;
+                skip = Some(path.span());
+            } else if path.is_ident("rename") {
+                duplicate(rename.is_some())?;
+                let lit = meta.value()?.parse::<LitStr>()?;
+                if lit.value().is_empty() {
+                    return Err(syn::Error::new(
+                        lit.span(),
+                        "argument name must not be empty",
+                    ));
+                }
+                rename = Some(lit);
+            } else if path.is_ident("default") {
+                duplicate(default.is_some())?;
+                default = Some(meta.value()?.parse::<Expr>()?);
+            } else {
+                return Err(meta.error(
+                    "unknown attribute, expected one of `positional`, \
+                     `named`, `rename`, `default` or `skip`",
+                ));
+            }
+            Ok(())
+        })?;
+    }
+
+    if skip.is_some() {
+        if let Some(other) = positional.or(named) {
+            return Err(syn::Error::new(
+                other,
+                "skipped fields cannot be passed to Typst",
+            ));
+        }
+        if let Some(rename) = &rename {
+            return Err(syn::Error::new(
+                rename.span(),
+                "skipped fields cannot be renamed",
+            ));
+        }
+        if let Some(default) = &default {
+            return Err(syn::Error::new(
+                default.span(),
+                "skipped fields cannot have a default",
+            ));
+        }
+        return Ok(None);
+    }
+
+    if let (Some(_), Some(named)) = (positional, named) {
+        return Err(syn::Error::new(
+            named,
+            "an argument cannot be both `positional` and `named`",
+        ));
+    }
+
+    let member = match &field.ident {
+        Some(ident) => Member::Named(ident.clone()),
+        None => Member::Unnamed(index.into()),
+    };
+
+    let kind = if named.is_some() || rename.is_some() {
+        if let (Some(positional), Some(_)) = (positional, &rename) {
+            return Err(syn::Error::new(
+                positional,
+                "positional arguments cannot be renamed",
+            ));
+        }
+
+        let name = match (rename, &field.ident) {
+            (Some(rename), _) => rename,
+            (None, Some(ident)) => {
+                LitStr::new(&ident.unraw().to_string(), ident.span())
+            }
+            (None, None) => {
+                return Err(syn::Error::new(
+                    named.unwrap_or_else(|| field.span()),
+                    "named arguments of tuple structs need \
+                     `rename = \"...\"`",
+                ));
+            }
+        };
+        ArgKind::Named(name)
+    } else {
+        ArgKind::Positional
+    };
+
+    let option = option_inner(&field.ty).cloned();
+    if let Some(default) = &default
+        && option.is_none()
+    {
+        return Err(syn::Error::new(
+            default.span(),
+            "`default` requires a field of type `Option<T>`",
+        ));
+    }
+
+    Ok(Some(FieldArg {
+        member,
+        kind,
+        option,
+        ty: field.ty.clone(),
+        default,
+        krate: krate.clone(),
+    }))
+}
+
+/// The `T` of an `Option<T>` type.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    if path.qself.is_some() {
+        return None;
+    }
+
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+
+    let PathArguments::AngleBracketed(args) = &segment.arguments
+    else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(inner) if args.args.len() == 1 => {
+            Some(inner)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,10 @@
+use velyst::func::TypstFunc;
+
+#[derive(TypstFunc)]
+#[typst(name = "card")]
+struct CardFunc {
+    #[typst(optional)]
+    title: String,
+}
+
+fn main() {}
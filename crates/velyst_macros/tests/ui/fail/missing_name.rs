@@ -0,0 +1,8 @@
+use velyst::func::TypstFunc;
+
+#[derive(TypstFunc)]
+struct CardFunc {
+    title: String,
+}
+
+fn main() {}
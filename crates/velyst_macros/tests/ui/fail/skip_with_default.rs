@@ -0,0 +1,10 @@
+use velyst::func::TypstFunc;
+
+#[derive(TypstFunc)]
+#[typst(name = "card")]
+struct CardFunc {
+    #[typst(skip, default = 1.0)]
+    scale: Option<f64>,
+}
+
+fn main() {}
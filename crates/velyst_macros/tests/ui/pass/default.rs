@@ -0,0 +1,29 @@
+use velyst::func::TypstFunc;
+use velyst::typst::foundations::IntoValue;
+
+#[derive(TypstFunc)]
+#[typst(name = "button")]
+struct ButtonFunc {
+    #[typst(default = 1.5)]
+    scale: Option<f64>,
+    #[typst(named, default = "Ok".to_string())]
+    label: Option<String>,
+}
+
+fn main() {
+    let mut func = ButtonFunc {
+        scale: None,
+        label: None,
+    };
+
+    let mut positional = Vec::new();
+    let mut named = Vec::new();
+    func.apply_positional_args(&mut positional);
+    func.apply_named_args(&mut named);
+    assert_eq!(positional, [1.5.into_value()]);
+    assert_eq!(named, [("label", "Ok".into_value())]);
+
+    func.label = Some("Cancel".into());
+    func.apply_named_args(&mut named);
+    assert_eq!(named, [("label", "Cancel".into_value())]);
+}
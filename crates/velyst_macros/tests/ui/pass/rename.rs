@@ -0,0 +1,26 @@
+use velyst::func::TypstFunc;
+use velyst::typst::foundations::IntoValue;
+
+#[derive(TypstFunc)]
+#[typst(name = "label")]
+struct LabelFunc {
+    #[typst(rename = "font-size")]
+    font_size: f64,
+    #[typst(named, rename = "fill-color")]
+    fill: Option<String>,
+}
+
+fn main() {
+    let func = LabelFunc {
+        font_size: 12.0,
+        fill: None,
+    };
+
+    let mut named = Vec::new();
+    func.apply_named_args(&mut named);
+    assert_eq!(named, [("font-size", 12.0.into_value())]);
+    assert_eq!(
+        LabelFunc::PARAMS.unwrap().named,
+        ["font-size", "fill-color"]
+    );
+}
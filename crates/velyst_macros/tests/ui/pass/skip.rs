@@ -0,0 +1,26 @@
+use velyst::func::TypstFunc;
+use velyst::typst::foundations::IntoValue;
+
+/// Not convertible into a Typst value.
+#[derive(Default)]
+struct Cache(Vec<u32>);
+
+#[derive(TypstFunc)]
+#[typst(name = "card")]
+struct CardFunc {
+    title: String,
+    #[typst(skip)]
+    _cache: Cache,
+}
+
+fn main() {
+    let func = CardFunc {
+        title: "Title".into(),
+        _cache: Default::default(),
+    };
+
+    let mut positional = Vec::new();
+    func.apply_positional_args(&mut positional);
+    assert_eq!(positional, ["Title".into_value()]);
+    assert_eq!(CardFunc::PARAMS.unwrap().positional, ["title"]);
+}
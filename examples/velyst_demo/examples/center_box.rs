@@ -26,8 +26,6 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     ));
 }
 
-typst_func!(
-    "main",
-    #[derive(Default)]
-    struct MainFunc {},
-);
+#[derive(TypstFunc, Default)]
+#[typst(name = "main")]
+struct MainFunc {}
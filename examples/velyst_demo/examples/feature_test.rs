@@ -90,8 +90,6 @@ fn pan_zoom_camera(
     }
 }
 
-typst_func!(
-    "feature_test",
-    #[derive(Default)]
-    struct FeatureTestFunc {},
-);
+#[derive(TypstFunc, Default)]
+#[typst(name = "feature_test")]
+struct FeatureTestFunc {}
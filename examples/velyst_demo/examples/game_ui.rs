@@ -1,5 +1,3 @@
-use std::str::FromStr;
-
 use bevy::prelude::*;
 use bevy_vello::prelude::*;
 use velyst::prelude::*;
@@ -25,13 +23,13 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     let handle = asset_server.load("typst/game_ui.typ");
 
     // Colors.
-    const RED: &str = "#FF6188";
-    const GREEN: &str = "#A9DC76";
-    const PURPLE: &str = "#AB9DF2";
+    const RED: Color = Color::srgb_u8(0xFF, 0x61, 0x88);
+    const GREEN: Color = Color::srgb_u8(0xA9, 0xDC, 0x76);
+    const PURPLE: Color = Color::srgb_u8(0xAB, 0x9D, 0xF2);
 
-    let green = viz::Color::from_str(GREEN).unwrap();
-    let purple = viz::Color::from_str(PURPLE).unwrap();
-    let red = viz::Color::from_str(RED).unwrap();
+    let green = GREEN.typst_color();
+    let purple = PURPLE.typst_color();
+    let red = RED.typst_color();
 
     // let debug_bg =
     // BackgroundColor(Srgba::RED.with_alpha(0.2).into());
@@ -131,26 +129,22 @@ fn button_interaction(
     }
 }
 
-typst_func!(
-    "perf_metrics",
-    #[derive(Default)]
-    struct PerfMetricsFunc {},
-    positional_args {
-        fps: f64,
-        elapsed_time: f64,
-    },
-);
-
-typst_func!(
-    "lbl",
-    #[derive(Default)]
-    struct LabelFunc {},
-    positional_args { body: Content },
-    named_args {
-        fill: viz::Color,
-        size: Abs,
-    }
-);
+#[derive(TypstFunc, Default)]
+#[typst(name = "perf_metrics")]
+struct PerfMetricsFunc {
+    fps: f64,
+    elapsed_time: f64,
+}
+
+#[derive(TypstFunc, Default)]
+#[typst(name = "lbl")]
+struct LabelFunc {
+    body: Content,
+    #[typst(named)]
+    fill: Option<viz::Color>,
+    #[typst(named)]
+    size: Option<Abs>,
+}
 
 impl LabelFunc {
     pub fn title(text: &str) -> Self {
@@ -162,19 +156,16 @@ impl LabelFunc {
     }
 }
 
-typst_func!(
-    "button",
-    #[derive(Default)]
-    struct ButtonFunc {},
-    positional_args {
-        body: Content,
-        interaction_state: u8,
-    },
-    named_args {
-        fill: viz::Color,
-        size: Abs,
-    }
-);
+#[derive(TypstFunc, Default)]
+#[typst(name = "button")]
+struct ButtonFunc {
+    body: Content,
+    interaction_state: u8,
+    #[typst(named)]
+    fill: Option<viz::Color>,
+    #[typst(named)]
+    size: Option<Abs>,
+}
 
 impl ButtonFunc {
     pub fn text(text: &str) -> Self {
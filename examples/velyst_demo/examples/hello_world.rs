@@ -49,9 +49,8 @@ fn main_func(
     Ok(())
 }
 
-typst_func!(
-    "main",
-    #[derive(Default)]
-    struct MainFunc {},
-    positional_args { animate: f64 },
-);
+#[derive(TypstFunc, Default)]
+#[typst(name = "main")]
+struct MainFunc {
+    animate: f64,
+}
@@ -110,9 +110,6 @@ fn animate_connections(
     }
 }
 
-typst_func!(
-    "main",
-    #[derive(Default)]
-    struct MainFunc {},
-    positional_args {},
-);
+#[derive(TypstFunc, Default)]
+#[typst(name = "main")]
+struct MainFunc {}